@@ -0,0 +1,90 @@
+use std::fs;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use porrs::{Outcome, Snapshot, Vm};
+
+/// Set when a snapshot has been requested with `SIGUSR1`
+static SNAPSHOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn listen_for_requests() {
+    extern "C" fn on_sigusr1(_: libc::c_int) {
+        SNAPSHOT_REQUESTED.store(true, Ordering::Relaxed);
+    }
+
+    // SAFETY: the handler only stores to an atomic
+    unsafe {
+        libc::signal(
+            libc::SIGUSR1,
+            on_sigusr1 as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn listen_for_requests() {}
+
+/// Writes `snapshot` next to `path` first, so that an interrupted write never
+/// clobbers the previous snapshot
+fn save(snapshot: &Snapshot, path: &Path) {
+    let mut tmp = PathBuf::from(path);
+    tmp.as_mut_os_string().push(".tmp");
+
+    match fs::write(&tmp, snapshot.to_bytes()).and_then(|()| fs::rename(&tmp, path)) {
+        Ok(()) => log::info!(
+            "Wrote snapshot after {} instructions to {}",
+            snapshot.executed(),
+            path.display()
+        ),
+        Err(err) => log::error!("Failed to write snapshot to {}: {}", path.display(), err),
+    }
+}
+
+/// Runs `vm`, optionally from the snapshot at `resume`, writing snapshots to
+/// `snapshot` every `every` instructions and whenever `SIGUSR1` is received
+pub fn run(
+    mut vm: Vm<'_>,
+    resume: Option<&Path>,
+    snapshot: Option<&Path>,
+    every: Option<NonZeroU64>,
+) -> porrs::Result<Outcome> {
+    let mut start = 0;
+
+    if let Some(path) = resume {
+        let snapshot = Snapshot::from_path(path)?;
+        start = snapshot.executed();
+        vm = vm.with_snapshot(snapshot)?;
+
+        log::info!(
+            "Resuming after {} instructions from {}",
+            start,
+            path.display()
+        );
+    }
+
+    let path = match snapshot {
+        Some(path) => path,
+        None => return vm.run(),
+    };
+
+    listen_for_requests();
+
+    let mut next_checkpoint = every.map(|every| start + every.get());
+    loop {
+        let outcome = vm.run_until(|executed| {
+            SNAPSHOT_REQUESTED.swap(false, Ordering::Relaxed)
+                || next_checkpoint.is_some_and(|next| executed >= next)
+        })?;
+
+        if let Some(outcome) = outcome {
+            return Ok(outcome);
+        }
+
+        let snapshot = vm.snapshot();
+        save(&snapshot, path);
+
+        next_checkpoint = every.map(|every| snapshot.executed() + every.get());
+    }
+}
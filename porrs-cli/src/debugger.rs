@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use porrs::{Bytecode, FileLocation, OpKind, OpView, OverflowPolicy, Program, Vm};
+
+use crate::{report_error, MessageFormat};
+
+const HELP: &str = "\
+Commands:
+  break [FILE:]LINE  (b)  Stop when execution reaches LINE
+  delete [N]         (d)  Delete breakpoint N, or all of them
+  info               (i)  List breakpoints
+  step               (s)  Execute one operation, stepping into blocks
+  next               (n)  Execute one operation, stepping over blocks
+  continue           (c)  Run until the next breakpoint
+  reverse-step       (rs) Undo the last operation
+  reverse-continue   (rc) Undo operations back to the previous breakpoint
+  history                 Show how many operations can be undone
+  stack              (st) Show the data stack, top last
+  quit               (q)  Stop the program and exit
+  help               (h)  Show this message";
+
+#[derive(Debug)]
+struct Breakpoint {
+    path: PathBuf,
+    line: usize,
+}
+
+impl Breakpoint {
+    fn parse(text: &str, current: Option<&FileLocation>) -> Option<Self> {
+        let (path, line) = match text.rsplit_once(':') {
+            Some((path, line)) => (PathBuf::from(path), line),
+            None => (current?.path().to_path_buf(), text),
+        };
+
+        Some(Self {
+            path,
+            line: line.parse().ok()?,
+        })
+    }
+
+    fn matches(&self, loc: &FileLocation) -> bool {
+        loc.line() == Some(self.line) && loc.path().ends_with(&self.path)
+    }
+}
+
+/// What to do once the user is done at the prompt
+#[derive(Debug)]
+enum Command {
+    Resume,
+    ReverseStep,
+    ReverseContinue,
+    Quit,
+}
+
+#[derive(Debug)]
+enum Mode {
+    Step,
+    Next { depth: usize },
+    Continue,
+}
+
+fn line_of(loc: &FileLocation) -> Option<(PathBuf, usize)> {
+    loc.line().map(|line| (loc.path().to_path_buf(), line))
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+    last_line: Option<(PathBuf, usize)>,
+    sources: HashMap<PathBuf, Vec<String>>,
+    format: MessageFormat,
+}
+
+impl Debugger {
+    pub fn new(breakpoints: &[String], format: MessageFormat) -> Self {
+        let mut debugger = Self {
+            breakpoints: Vec::new(),
+            mode: Mode::Step,
+            last_line: None,
+            sources: HashMap::new(),
+            format,
+        };
+
+        for text in breakpoints {
+            debugger.add_breakpoint(text, None);
+        }
+
+        if !debugger.breakpoints.is_empty() {
+            debugger.mode = Mode::Continue;
+        }
+
+        debugger
+    }
+
+    fn add_breakpoint(&mut self, text: &str, current: Option<&FileLocation>) {
+        match Breakpoint::parse(text, current) {
+            Some(breakpoint) => {
+                println!(
+                    "Breakpoint {} at {}:{}",
+                    self.breakpoints.len() + 1,
+                    breakpoint.path.display(),
+                    breakpoint.line
+                );
+                self.breakpoints.push(breakpoint);
+            }
+            None => println!("Invalid breakpoint `{}`, expected [FILE:]LINE", text),
+        }
+    }
+
+    /// Runs `program` under the debugger, keeping the last `history`
+    /// operations so they can be undone. Returns the code the program gave to
+    /// `exit`, if it stopped through it before quitting.
+    pub fn run(
+        &mut self,
+        program: &Program,
+        history: usize,
+        overflow: OverflowPolicy,
+    ) -> Option<i32> {
+        let bytecode = Bytecode::from_program(program);
+        // Commands are read from stdin, so the program gets no input
+        let mut vm = Vm::new(&bytecode)
+            .with_input(io::empty())
+            .with_overflow(overflow)
+            .with_history(history);
+
+        let mut force_stop = false;
+
+        loop {
+            let stop = match vm.op() {
+                Some(op) => {
+                    let line = line_of(op.location());
+                    let entered_line = line != self.last_line;
+                    self.last_line = line;
+
+                    !matches!(op.kind(), OpKind::Jump)
+                        && (force_stop || self.should_stop(&op, entered_line))
+                }
+                None => true,
+            };
+            force_stop = false;
+
+            if stop {
+                match vm.op() {
+                    Some(op) => self.show_op(&op),
+                    None => match vm.exit_code() {
+                        Some(code) => println!("Program exited with code {}", code),
+                        None => println!("Program finished"),
+                    },
+                }
+
+                match self.prompt(&vm) {
+                    Command::Resume => {}
+                    Command::ReverseStep => {
+                        self.reverse(&mut vm, false);
+                        force_stop = true;
+                        continue;
+                    }
+                    Command::ReverseContinue => {
+                        self.reverse(&mut vm, true);
+                        force_stop = true;
+                        continue;
+                    }
+                    Command::Quit => return vm.exit_code(),
+                }
+            }
+
+            if let Err(err) = vm.step() {
+                report_error(&err, self.format);
+                force_stop = true;
+            }
+        }
+    }
+
+    /// Undoes one operation, or with `to_breakpoint` every operation back to
+    /// the last one which entered a breakpoint's line
+    fn reverse(&mut self, vm: &mut Vm<'_>, to_breakpoint: bool) {
+        loop {
+            if !vm.step_back() {
+                println!("No more history to undo");
+                break;
+            }
+
+            let op = vm
+                .op()
+                .expect("an undone operation should be in the program");
+            if matches!(op.kind(), OpKind::Jump) {
+                continue;
+            }
+
+            let entered_line = vm
+                .previous_op()
+                .is_none_or(|prev| line_of(prev.location()) != line_of(op.location()));
+
+            let at_breakpoint = entered_line
+                && self
+                    .breakpoints
+                    .iter()
+                    .any(|breakpoint| breakpoint.matches(op.location()));
+
+            if !to_breakpoint || at_breakpoint {
+                break;
+            }
+        }
+
+        self.last_line = vm.op().and_then(|op| line_of(op.location()));
+    }
+
+    fn should_stop(&self, op: &OpView<'_>, entered_line: bool) -> bool {
+        match self.mode {
+            Mode::Step => true,
+            Mode::Next { depth } => op.depth() <= depth,
+            Mode::Continue => {
+                entered_line
+                    && self
+                        .breakpoints
+                        .iter()
+                        .any(|breakpoint| breakpoint.matches(op.location()))
+            }
+        }
+    }
+
+    fn source_line(&mut self, path: &Path, line: usize) -> Option<&str> {
+        let lines = self.sources.entry(path.to_path_buf()).or_insert_with(|| {
+            fs::read_to_string(path)
+                .map(|text| text.lines().map(str::to_owned).collect())
+                .unwrap_or_default()
+        });
+
+        lines.get(line.checked_sub(1)?).map(String::as_str)
+    }
+
+    fn show_op(&mut self, op: &OpView<'_>) {
+        let loc = op.location();
+        println!("`{}` at {}", op.kind(), loc);
+
+        if let Some(pos) = loc.position() {
+            if let Some(text) = self.source_line(loc.path(), pos.line()) {
+                let text = text.to_owned();
+                let gutter = format!("{} | ", pos.line());
+                println!("{}{}", gutter, text);
+                println!(
+                    "{:width$}{}",
+                    "",
+                    "^".repeat(pos.span_len().max(1)),
+                    width = gutter.len() + pos.column() - 1
+                );
+            }
+        }
+    }
+
+    fn prompt(&mut self, vm: &Vm<'_>) -> Command {
+        let op = vm.op();
+        let stdin = io::stdin();
+        let mut input = String::new();
+
+        loop {
+            print!("(dbg) ");
+            let _ = io::stdout().flush();
+
+            input.clear();
+            match stdin.lock().read_line(&mut input) {
+                Ok(0) | Err(_) => return Command::Quit,
+                Ok(_) => {}
+            }
+
+            let mut args = input.split_whitespace();
+            match (args.next(), args.next()) {
+                (Some("break" | "b"), Some(text)) => {
+                    self.add_breakpoint(text, op.map(|op| op.location()))
+                }
+
+                (Some("delete" | "d"), None) => self.breakpoints.clear(),
+                (Some("delete" | "d"), Some(n)) => match n.parse::<usize>() {
+                    Ok(n) if (1..=self.breakpoints.len()).contains(&n) => {
+                        self.breakpoints.remove(n - 1);
+                    }
+                    _ => println!("No breakpoint `{}`", n),
+                },
+
+                (Some("info" | "i"), None) => {
+                    for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+                        println!(
+                            "{}: {}:{}",
+                            i + 1,
+                            breakpoint.path.display(),
+                            breakpoint.line
+                        );
+                    }
+                }
+
+                (Some("step" | "s" | "next" | "n" | "continue" | "c"), None) if op.is_none() => {
+                    println!("The program has finished, reverse or quit");
+                }
+
+                (Some("step" | "s"), None) => {
+                    self.mode = Mode::Step;
+                    return Command::Resume;
+                }
+
+                (Some("next" | "n"), None) => {
+                    self.mode = Mode::Next {
+                        depth: op.map_or(0, |op| op.depth()),
+                    };
+                    return Command::Resume;
+                }
+
+                (Some("continue" | "c"), None) => {
+                    self.mode = Mode::Continue;
+                    return Command::Resume;
+                }
+
+                (Some("reverse-step" | "rs"), None) => return Command::ReverseStep,
+
+                (Some("reverse-continue" | "rc"), None) => return Command::ReverseContinue,
+
+                (Some("history"), None) => println!(
+                    "{} operations can be undone, using {} bytes",
+                    vm.history_len(),
+                    vm.history_bytes()
+                ),
+
+                (Some("stack" | "st"), None) => println!("{:?}", vm.stack()),
+
+                (Some("quit" | "q"), None) => return Command::Quit,
+
+                (Some("help" | "h"), None) => println!("{}", HELP),
+
+                (None, _) => {}
+
+                _ => println!("Unknown command `{}`, try `help`", input.trim()),
+            }
+        }
+    }
+}
@@ -1,27 +1,257 @@
+mod checkpoint;
+mod debugger;
+mod repl;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroU64;
 use std::path;
 use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{AppSettings, ArgEnum, ArgGroup, Args, CommandFactory, ErrorKind, Parser, Subcommand};
 
-use clap::{AppSettings, Parser, Subcommand};
+use debugger::Debugger;
 
 #[derive(Debug, Subcommand)]
 pub enum ExecutionMode {
     /// Simulate the provided program
     #[clap(name = "sim")]
-    Simulate,
+    #[clap(group(
+        ArgGroup::new("observers")
+            .multiple(true)
+            .args(&["trace", "profile", "profile-folded", "coverage"])
+    ))]
+    Simulate {
+        /// Compile to bytecode and run it in a flat interpreter loop, which is
+        /// faster but cannot be traced, profiled or covered
+        #[clap(long, conflicts_with = "observers")]
+        bytecode: bool,
+
+        /// Compile to machine code and run it in-process, falling back to the
+        /// bytecode interpreter for operations it cannot run. Only supported
+        /// on x86-64 Linux, elsewhere this is the same as `--bytecode`
+        #[clap(long, conflicts_with_all = &["observers", "limits"])]
+        jit: bool,
+
+        /// Write a snapshot to FILE whenever SIGUSR1 is received, to resume
+        /// from with `--resume`. Runs the program as bytecode
+        #[clap(
+            long,
+            value_name = "FILE",
+            parse(from_os_str),
+            conflicts_with_all = &["observers", "jit"]
+        )]
+        snapshot: Option<path::PathBuf>,
+
+        /// Also write a snapshot every N executed instructions
+        #[clap(long, value_name = "N", requires = "snapshot")]
+        checkpoint_every: Option<NonZeroU64>,
+
+        /// Resume from a snapshot of the same program. Runs the program as
+        /// bytecode
+        #[clap(
+            long,
+            value_name = "FILE",
+            parse(from_os_str),
+            conflicts_with_all = &["observers", "jit"]
+        )]
+        resume: Option<path::PathBuf>,
+
+        /// Record every executed operation and branch to FILE as JSON lines
+        #[clap(long, value_name = "FILE", parse(from_os_str))]
+        trace: Option<path::PathBuf>,
+
+        #[clap(flatten)]
+        limits: LimitArgs,
+
+        /// Print the hottest lines and blocks once the program finishes
+        #[clap(long)]
+        profile: bool,
+
+        /// Number of entries in each table printed by `--profile`
+        #[clap(long, value_name = "N", default_value = "10")]
+        profile_top: usize,
+
+        /// Write the profile to FILE as folded stacks for flamegraph tools
+        #[clap(long, value_name = "FILE", parse(from_os_str))]
+        profile_folded: Option<path::PathBuf>,
+
+        /// Write line and branch coverage to FILE as an lcov tracefile
+        #[clap(long, value_name = "FILE", parse(from_os_str))]
+        coverage: Option<path::PathBuf>,
+
+        /// What to do when the program finishes with values left on the
+        /// stack. Where they were pushed is only reported without
+        /// `--bytecode`, `--jit`, `--snapshot` and `--resume`
+        #[clap(long, arg_enum, value_name = "POLICY", default_value = "warn")]
+        leftover: LeftoverPolicy,
+    },
+
+    /// Step through the provided program in an interactive debugger
+    #[clap(name = "dbg")]
+    Debug {
+        /// Stop when execution reaches this line, as `[FILE:]LINE`
+        #[clap(short, long = "break", value_name = "LOCATION")]
+        breakpoints: Vec<String>,
 
-    /// Compile the provided program to binary (Not implemented)
+        /// Keep the last N operations so they can be undone with
+        /// `reverse-step` and `reverse-continue`
+        #[clap(long, value_name = "N", default_value = "10000")]
+        history: usize,
+    },
+
+    /// Read, parse and run lines interactively
+    #[clap(name = "repl")]
+    Repl,
+
+    /// Compile the provided program
     #[clap(name = "com")]
-    NativeCompile,
+    Compile {
+        /// Kind of output to produce
+        #[clap(long, arg_enum, default_value = "bytecode")]
+        emit: Emit,
+
+        /// Write the output to FILE, by default the source file with the
+        /// extension of the output kind
+        #[clap(short, value_name = "FILE", parse(from_os_str))]
+        output: Option<path::PathBuf>,
+    },
+
+    /// Run a program compiled with `com --emit=bytecode`
+    #[clap(name = "run")]
+    Run {
+        #[clap(flatten)]
+        limits: LimitArgs,
+
+        /// What to do when the program finishes with values left on the
+        /// stack. Where they were pushed is not known for compiled programs
+        #[clap(long, arg_enum, value_name = "POLICY", default_value = "warn")]
+        leftover: LeftoverPolicy,
+    },
+}
+
+/// Limits stopping a program which runs away. When running bytecode, these
+/// count instructions rather than operations.
+#[derive(Debug, Args)]
+#[clap(group(
+    ArgGroup::new("limits")
+        .multiple(true)
+        .args(&["max-ops", "max-stack-depth", "max-memory", "timeout"])
+))]
+pub struct LimitArgs {
+    /// Stop after executing more than N operations
+    #[clap(long, value_name = "N")]
+    max_ops: Option<u64>,
+
+    /// Stop once the stack holds more than N values
+    #[clap(long, value_name = "N")]
+    max_stack_depth: Option<usize>,
+
+    /// Stop once the program uses more than BYTES of memory
+    #[clap(long, value_name = "BYTES")]
+    max_memory: Option<usize>,
+
+    /// Stop after running for more than SECONDS
+    #[clap(long, value_name = "SECONDS", parse(try_from_str = parse_duration))]
+    timeout: Option<Duration>,
+}
+
+impl LimitArgs {
+    fn limits(&self) -> porrs::Limits {
+        let mut limits = porrs::Limits::new();
+        if let Some(max_ops) = self.max_ops {
+            limits = limits.max_ops(max_ops);
+        }
+        if let Some(max_stack_depth) = self.max_stack_depth {
+            limits = limits.max_stack_depth(max_stack_depth);
+        }
+        if let Some(max_memory) = self.max_memory {
+            limits = limits.max_memory(max_memory);
+        }
+        if let Some(timeout) = self.timeout {
+            limits = limits.timeout(timeout);
+        }
+        limits
+    }
+}
+
+#[derive(Clone, Copy, Debug, ArgEnum)]
+pub enum Emit {
+    /// `.porbc` bytecode, run with the `run` subcommand
+    Bytecode,
+}
+
+#[derive(Clone, Copy, Debug, ArgEnum)]
+pub enum LeftoverPolicy {
+    /// Discard the leftover values
+    Ignore,
+
+    /// Report the leftover values as a warning
+    Warn,
+
+    /// Report the leftover values as an error
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, ArgEnum)]
+pub enum Overflow {
+    /// Wrap around silently
+    Wrap,
+
+    /// Wrap around and print a warning
+    Warn,
+
+    /// Stop the program with an error
+    Trap,
+}
+
+impl From<Overflow> for porrs::OverflowPolicy {
+    fn from(overflow: Overflow) -> Self {
+        match overflow {
+            Overflow::Wrap => Self::Wrap,
+            Overflow::Warn => Self::Warn,
+            Overflow::Trap => Self::Trap,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ArgEnum)]
+pub enum MessageFormat {
+    /// `<-- path:line:col -->` prefixed messages
+    Human,
+
+    /// `path:line:col: severity: message`, as understood by most editors
+    Short,
 }
 
 #[derive(Debug, Parser)]
 #[clap(version)]
 #[clap(about = "Porth compiler / simulator in Rust", long_about = None)]
 #[clap(global_setting(AppSettings::DeriveDisplayOrder))]
+#[clap(after_help = "EXIT STATUS:
+    The code given to `exit` by the program, 0 if it never calls it, or on error:
+    65  lexing error
+    66  parsing error
+    67  bytecode, snapshot or recording failed to load
+    70  simulation error
+    1   any other failure
+    A program can exit with these codes itself. Errors are always reported on
+    stderr, which tells them apart.")]
 pub struct Config {
-    /// Porth source file
+    /// Porth source file, or bytecode file for `run`. Optional only for
+    /// `repl`, where it is run first
     #[clap(parse(from_os_str))]
-    pub source_file: path::PathBuf,
+    pub source_file: Option<path::PathBuf>,
+
+    /// Format of the reported errors
+    #[clap(long, arg_enum, global = true, default_value = "human")]
+    pub message_format: MessageFormat,
+
+    /// What happens when `+`, `-` or `*` overflows
+    #[clap(long, arg_enum, global = true, default_value = "warn")]
+    pub overflow: Overflow,
 
     #[clap(subcommand)]
     pub execution_mode: ExecutionMode,
@@ -37,12 +267,329 @@ fn init_logger() {
     Builder::from_env(env).format_timestamp(None).init();
 }
 
-fn run(config: &Config) -> Result<(), porrs::Error> {
-    let program = porrs::Program::from_path(&config.source_file)?;
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    text.parse::<f64>()
+        .map_err(|err| err.to_string())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).map_err(|err| err.to_string()))
+}
+
+fn create_file(path: &path::Path) -> BufWriter<File> {
+    match File::create(path) {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => {
+            log::error!("Failed to create {}: {}", path.display(), err);
+            exit(1);
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn run_jit(
+    bytecode: &porrs::Bytecode,
+    overflow: porrs::OverflowPolicy,
+) -> Result<porrs::Outcome, porrs::Error> {
+    let mut vm = porrs::Vm::new(bytecode).with_overflow(overflow);
+
+    match porrs::Jit::compile(bytecode) {
+        Ok(jit) => {
+            let outcome = vm.with_jit(&jit).run()?;
+            Ok(outcome)
+        }
+        Err(err) => {
+            log::warn!("Failed to map JIT code, falling back to bytecode: {}", err);
+            vm.run()
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+fn run_jit(
+    bytecode: &porrs::Bytecode,
+    overflow: porrs::OverflowPolicy,
+) -> Result<porrs::Outcome, porrs::Error> {
+    log::warn!("The JIT is only supported on x86-64 Linux, falling back to bytecode");
+    porrs::Vm::new(bytecode).with_overflow(overflow).run()
+}
 
-    match config.execution_mode {
-        ExecutionMode::Simulate => porrs::simulate(&program),
-        ExecutionMode::NativeCompile => unimplemented!("File compilation is not yet implemented"),
+/// Exit status of the process when `err` stops it, distinct per category
+fn error_status(err: &porrs::Error) -> i32 {
+    match err.category() {
+        porrs::Category::Lexing => 65,
+        porrs::Category::Parsing => 66,
+        porrs::Category::Loading => 67,
+        porrs::Category::Simulation => 70,
+        _ => 1,
+    }
+}
+
+/// Runs what `config` asks for, returning the exit status of the process
+fn run(config: &Config) -> Result<i32, porrs::Error> {
+    let source_file = match (&config.execution_mode, &config.source_file) {
+        (ExecutionMode::Repl, source_file) => {
+            return repl::run(
+                source_file.as_deref(),
+                config.message_format,
+                config.overflow.into(),
+            );
+        }
+        (_, Some(source_file)) => source_file,
+        (_, None) => Config::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "A source file is required outside of `repl`",
+            )
+            .exit(),
+    };
+
+    if let ExecutionMode::Run { limits, leftover } = &config.execution_mode {
+        let bytecode = porrs::Bytecode::from_path(source_file, Arc::default())?;
+        let outcome = porrs::Vm::new(&bytecode)
+            .with_overflow(config.overflow.into())
+            .with_limits(limits.limits())
+            .run()?;
+        check_leftovers(None, &outcome, *leftover, config.message_format)?;
+        return Ok(outcome.exit_code.unwrap_or(0));
+    }
+
+    let program = porrs::Program::from_path(source_file)?;
+    let format = config.message_format;
+    let overflow = config.overflow.into();
+
+    match &config.execution_mode {
+        ExecutionMode::Simulate {
+            jit: true,
+            leftover,
+            ..
+        } => {
+            let bytecode = porrs::Bytecode::from_program(&program);
+            let outcome = run_jit(&bytecode, overflow)?;
+            check_leftovers(
+                Some(&porrs::Leftovers::new(&program)),
+                &outcome,
+                *leftover,
+                format,
+            )?;
+            Ok(outcome.exit_code.unwrap_or(0))
+        }
+        ExecutionMode::Simulate {
+            snapshot,
+            checkpoint_every,
+            resume,
+            limits,
+            leftover,
+            ..
+        } if snapshot.is_some() || resume.is_some() => {
+            let bytecode = porrs::Bytecode::from_program(&program);
+            let vm = porrs::Vm::new(&bytecode)
+                .with_overflow(overflow)
+                .with_limits(limits.limits());
+            let outcome = checkpoint::run(
+                vm,
+                resume.as_deref(),
+                snapshot.as_deref(),
+                *checkpoint_every,
+            )?;
+            check_leftovers(
+                Some(&porrs::Leftovers::new(&program)),
+                &outcome,
+                *leftover,
+                format,
+            )?;
+            Ok(outcome.exit_code.unwrap_or(0))
+        }
+        ExecutionMode::Simulate {
+            bytecode: true,
+            limits,
+            leftover,
+            ..
+        } => {
+            let bytecode = porrs::Bytecode::from_program(&program);
+            let outcome = porrs::Vm::new(&bytecode)
+                .with_overflow(overflow)
+                .with_limits(limits.limits())
+                .run()?;
+            check_leftovers(
+                Some(&porrs::Leftovers::new(&program)),
+                &outcome,
+                *leftover,
+                format,
+            )?;
+            Ok(outcome.exit_code.unwrap_or(0))
+        }
+        ExecutionMode::Simulate {
+            bytecode: false,
+            jit: false,
+            trace,
+            limits,
+            profile,
+            profile_top,
+            profile_folded,
+            coverage,
+            leftover,
+            ..
+        } => {
+            let mut observers: Vec<&mut dyn porrs::Observer> = Vec::new();
+
+            let mut limits = limits.limits();
+            observers.push(&mut limits);
+
+            let mut tracer = trace
+                .as_ref()
+                .map(|path| porrs::Tracer::new(create_file(path)));
+            if let Some(tracer) = tracer.as_mut() {
+                observers.push(tracer);
+            }
+
+            let mut profiler = (*profile || profile_folded.is_some()).then(porrs::Profiler::new);
+            if let Some(profiler) = profiler.as_mut() {
+                observers.push(profiler);
+            }
+
+            let mut coverage_observer = coverage.as_ref().map(|_| porrs::Coverage::new(&program));
+            if let Some(coverage_observer) = coverage_observer.as_mut() {
+                observers.push(coverage_observer);
+            }
+
+            let mut leftovers = porrs::Leftovers::new(&program);
+            if !matches!(leftover, LeftoverPolicy::Ignore) {
+                observers.push(&mut leftovers);
+            }
+
+            let result = porrs::Simulator::new(&program)
+                .with_overflow(overflow)
+                .with_observer(&mut observers)
+                .run();
+            let result = result.and_then(|outcome| {
+                check_leftovers(Some(&leftovers), &outcome, *leftover, format)?;
+                Ok(outcome.exit_code.unwrap_or(0))
+            });
+
+            if let Some(profiler) = profiler {
+                if *profile {
+                    let _ = profiler.write_report(&mut io::stderr(), *profile_top);
+                }
+
+                if let Some(path) = profile_folded {
+                    let mut file = create_file(path);
+                    if let Err(err) = profiler.write_folded(&mut file).and_then(|()| file.flush()) {
+                        log::error!("Failed to write profile to {}: {}", path.display(), err);
+                    }
+                }
+            }
+
+            if let (Some(tracer), Some(path)) = (tracer, trace) {
+                if let Err(err) = tracer.finish() {
+                    log::error!("Failed to write trace to {}: {}", path.display(), err);
+                }
+            }
+
+            if let (Some(coverage_observer), Some(path)) = (coverage_observer, coverage) {
+                let mut file = create_file(path);
+                if let Err(err) = coverage_observer
+                    .write_lcov(&mut file)
+                    .and_then(|()| file.flush())
+                {
+                    log::error!("Failed to write coverage to {}: {}", path.display(), err);
+                }
+            }
+
+            result
+        }
+        ExecutionMode::Debug {
+            breakpoints,
+            history,
+        } => {
+            let exit_code =
+                Debugger::new(breakpoints, config.message_format).run(&program, *history, overflow);
+            Ok(exit_code.unwrap_or(0))
+        }
+        ExecutionMode::Compile {
+            emit: Emit::Bytecode,
+            output,
+        } => {
+            let path = output
+                .clone()
+                .unwrap_or_else(|| source_file.with_extension("porbc"));
+
+            let bytes = porrs::Bytecode::from_program(&program).to_bytes();
+
+            let mut file = create_file(&path);
+            if let Err(err) = file.write_all(&bytes).and_then(|()| file.flush()) {
+                log::error!("Failed to write bytecode to {}: {}", path.display(), err);
+                exit(1);
+            }
+
+            log::info!("Wrote bytecode to file: {}", path.display());
+            Ok(0)
+        }
+        ExecutionMode::Repl | ExecutionMode::Run { .. } => unreachable!(),
+    }
+}
+
+/// Applies `policy` to the values `outcome` left on the stack, using
+/// `leftovers` to tell where they were pushed if the program is at hand.
+/// Programs stopping through `exit` may leave anything behind.
+fn check_leftovers(
+    leftovers: Option<&porrs::Leftovers>,
+    outcome: &porrs::Outcome,
+    policy: LeftoverPolicy,
+    format: MessageFormat,
+) -> Result<(), porrs::Error> {
+    if outcome.exit_code.is_some() {
+        return Ok(());
+    }
+
+    let result = match leftovers {
+        Some(leftovers) => leftovers.check(&outcome.stack),
+        None if outcome.stack.is_empty() => Ok(()),
+        None => Err(porrs::Error::from(porrs::SimulationError::LeftoverValues(
+            outcome.stack.len(),
+        ))),
+    };
+
+    match (policy, result) {
+        (LeftoverPolicy::Ignore, _) | (_, Ok(())) => Ok(()),
+        (LeftoverPolicy::Warn, Err(err)) => {
+            report(&err, format, Severity::Warning);
+            Ok(())
+        }
+        (LeftoverPolicy::Error, Err(err)) => Err(err),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+fn report_error(err: &porrs::Error, format: MessageFormat) {
+    report(err, format, Severity::Error)
+}
+
+fn report(err: &porrs::Error, format: MessageFormat, severity: Severity) {
+    let (label, severity) = match severity {
+        Severity::Error => ("ERROR", "error"),
+        Severity::Warning => ("WARN ", "warning"),
+    };
+
+    match format {
+        MessageFormat::Human => {
+            eprintln!("{} | {}", label, err);
+            for info in err.info_stack() {
+                eprintln!("NOTE  | {}", info)
+            }
+        }
+
+        MessageFormat::Short => {
+            match err.location() {
+                Some(loc) => eprintln!("{}: {}: {}", loc, severity, err.kind()),
+                None => eprintln!("{}: {}: {}", env!("CARGO_PKG_NAME"), severity, err.kind()),
+            }
+            for info in err.info_stack() {
+                eprintln!("{}: note: {}", info.location(), info.kind())
+            }
+        }
     }
 }
 
@@ -52,12 +599,11 @@ fn main() {
     let config = Config::parse();
     log::debug!("CLI Config: {:#?}", config);
 
-    if let Err(err) = run(&config) {
-        eprintln!("ERROR | {}", err);
-        for info in err.info_stack() {
-            eprintln!("NOTE  | {}", info)
+    match run(&config) {
+        Ok(status) => exit(status),
+        Err(err) => {
+            report_error(&err, config.message_format);
+            exit(error_status(&err));
         }
-
-        exit(1);
     }
 }
@@ -0,0 +1,84 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use porrs::{ErrorKind, Marker, MissingMarker, OverflowPolicy, ParsingError, Program, Simulator};
+
+use crate::{report_error, MessageFormat};
+
+const SOURCE_NAME: &str = "<repl>";
+
+/// Whether `err` only means that a block is still waiting for its `end`
+fn is_incomplete(err: &porrs::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::Parsing(ParsingError::MissingMarker(
+            Marker::End,
+            MissingMarker::BlockNotClosed
+        ))
+    )
+}
+
+/// Runs `program` on `stack`, leaving the stack untouched if it fails.
+/// Returns the code given to `exit`, if the program called it
+fn run_on(
+    program: &Program,
+    stack: &mut Vec<u64>,
+    overflow: OverflowPolicy,
+) -> porrs::Result<Option<i32>> {
+    let outcome = Simulator::new(program)
+        .with_stack(stack.clone())
+        .with_overflow(overflow)
+        .run()?;
+    *stack = outcome.stack;
+
+    Ok(outcome.exit_code)
+}
+
+/// Runs the REPL until the input ends or a line calls `exit`, returning the
+/// code the session should exit with
+pub fn run(
+    prelude: Option<&Path>,
+    format: MessageFormat,
+    overflow: OverflowPolicy,
+) -> porrs::Result<i32> {
+    let mut stack = Vec::new();
+
+    if let Some(path) = prelude {
+        if let Some(code) = run_on(&Program::from_path(path)?, &mut stack, overflow)? {
+            return Ok(code);
+        }
+        println!("{:?}", stack);
+    }
+
+    let stdin = io::stdin();
+    let mut source = String::new();
+
+    loop {
+        print!("{}", if source.is_empty() { "porrs> " } else { "  ...> " });
+        let _ = io::stdout().flush();
+
+        match stdin.lock().read_line(&mut source) {
+            Ok(0) => {
+                println!();
+                return Ok(0);
+            }
+            Ok(_) => {}
+            Err(err) => {
+                log::error!("Failed to read input: {}", err);
+                return Ok(0);
+            }
+        }
+
+        let result = Program::from_source(SOURCE_NAME, source.as_str())
+            .and_then(|program| run_on(&program, &mut stack, overflow));
+
+        match result {
+            Err(err) if is_incomplete(&err) => continue,
+            Err(err) => report_error(&err, format),
+            Ok(Some(code)) => return Ok(code),
+            Ok(None) => println!("{:?}", stack),
+        }
+
+        source.clear();
+    }
+}
@@ -0,0 +1,63 @@
+//! Compares the tree-walking [`Simulator`] with the bytecode [`Vm`] and its
+//! JIT, run with `cargo bench -p porrs`
+
+#![feature(test)]
+
+extern crate test;
+
+use std::io;
+
+use porrs::{Bytecode, Program, Simulator, Vm};
+use test::Bencher;
+
+/// Counts down from 10000 in a loop branching through an `if*` chain on every
+/// iteration
+const COUNT_LOOP: &str = "
+10000 while dup do
+    dup 2 divmod swap drop if
+        0 drop
+    else dup 2 divmod drop 2 divmod swap drop if*
+        1 drop
+    else
+        2 drop
+    end
+    1 -
+end
+drop
+";
+
+fn count_loop() -> Program {
+    Program::from_source("<bench>", COUNT_LOOP).expect("benchmark program should parse")
+}
+
+#[bench]
+fn tree_walker(b: &mut Bencher) {
+    let program = count_loop();
+
+    b.iter(|| Simulator::new(&program).with_output(io::sink()).run().unwrap());
+}
+
+#[bench]
+fn bytecode_vm(b: &mut Bencher) {
+    let program = count_loop();
+    let bytecode = Bytecode::from_program(&program);
+
+    b.iter(|| Vm::new(&bytecode).with_output(io::sink()).run().unwrap());
+}
+
+#[bench]
+fn bytecode_compile(b: &mut Bencher) {
+    let program = count_loop();
+
+    b.iter(|| Bytecode::from_program(&program));
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[bench]
+fn jit(b: &mut Bencher) {
+    let program = count_loop();
+    let bytecode = Bytecode::from_program(&program);
+    let jit = porrs::Jit::compile(&bytecode).unwrap();
+
+    b.iter(|| Vm::new(&bytecode).with_jit(&jit).with_output(io::sink()).run().unwrap());
+}
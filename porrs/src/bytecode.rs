@@ -0,0 +1,201 @@
+use std::sync::Arc;
+
+use crate::native::{NativeId, Natives};
+use crate::op::{If, Intrinsic, Op, OpBlock, OpId, OpType, While};
+use crate::program::{FileLocation, Program};
+use crate::token::Marker;
+
+/// Index of an instruction within [`Bytecode`]
+pub(crate) type Addr = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Instr {
+    PushInt(u64),
+    Intrinsic(Intrinsic),
+    Native(NativeId),
+    /// Pops the condition guarding the block after `marker`, continuing at
+    /// `target` if it is false
+    Branch { marker: Marker, target: Addr },
+    Jump(Addr),
+}
+
+/// An `if` or `while` which instructions are nested within, used to rebuild the
+/// backtrace of an error
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Block {
+    pub(crate) marker: Marker,
+    pub(crate) loc: FileLocation,
+    pub(crate) parent: Option<usize>,
+}
+
+/// A [`Program`] lowered to a flat list of instructions with resolved jump
+/// targets, to be run by a [`Vm`](crate::Vm).
+///
+/// Each instruction keeps the location of the operation it came from and the
+/// innermost block it is nested in, so errors are reported exactly as the
+/// [`Simulator`](crate::Simulator) reports them.
+#[derive(Debug)]
+pub struct Bytecode {
+    pub(crate) instrs: Vec<Instr>,
+    pub(crate) locs: Vec<FileLocation>,
+    /// Operation each instruction was compiled from
+    pub(crate) ids: Vec<OpId>,
+    pub(crate) parents: Vec<Option<usize>>,
+    pub(crate) blocks: Vec<Block>,
+    pub(crate) natives: Arc<Natives>,
+}
+
+impl Bytecode {
+    pub fn from_program(program: &Program) -> Self {
+        let mut bytecode = Self {
+            instrs: Vec::new(),
+            locs: Vec::new(),
+            ids: Vec::new(),
+            parents: Vec::new(),
+            blocks: Vec::new(),
+            natives: Arc::clone(&program.natives),
+        };
+
+        bytecode.compile_block(&program.root_block, None);
+
+        log::debug!("Compiled program to {} instructions", bytecode.len());
+
+        bytecode
+    }
+
+    /// Number of instructions
+    pub fn len(&self) -> usize {
+        self.instrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instrs.is_empty()
+    }
+
+    fn emit(&mut self, instr: Instr, loc: &FileLocation, id: OpId, parent: Option<usize>) -> Addr {
+        self.instrs.push(instr);
+        self.locs.push(loc.clone());
+        self.ids.push(id);
+        self.parents.push(parent);
+        self.instrs.len() - 1
+    }
+
+    /// Points the `Branch` or `Jump` at `addr` to the next instruction emitted
+    fn patch(&mut self, addr: Addr) {
+        let next = self.instrs.len();
+
+        match &mut self.instrs[addr] {
+            Instr::Branch { target, .. } | Instr::Jump(target) => *target = next,
+            instr => unreachable!("{:?} has no jump target", instr),
+        }
+    }
+
+    fn open_block(&mut self, marker: Marker, loc: &FileLocation, parent: Option<usize>) -> usize {
+        self.blocks.push(Block {
+            marker,
+            loc: loc.clone(),
+            parent,
+        });
+        self.blocks.len() - 1
+    }
+
+    fn compile_block(&mut self, op_block: &OpBlock, parent: Option<usize>) {
+        for op in op_block.iter() {
+            self.compile_op(op, parent);
+        }
+    }
+
+    fn compile_op(&mut self, op: &Op, parent: Option<usize>) {
+        match &op.typ {
+            OpType::PushInt(val) => {
+                self.emit(Instr::PushInt(*val), &op.loc, op.id, parent);
+            }
+
+            OpType::Intrinsic(intr) => {
+                self.emit(Instr::Intrinsic(*intr), &op.loc, op.id, parent);
+            }
+
+            OpType::Native(id, _) => {
+                self.emit(Instr::Native(*id), &op.loc, op.id, parent);
+            }
+
+            OpType::If(if_op) => {
+                let block = self.open_block(Marker::If, &op.loc, parent);
+                self.compile_if(op, if_op, Some(block));
+            }
+
+            OpType::While(while_op) => {
+                let block = self.open_block(Marker::While, &op.loc, parent);
+                self.compile_while(op, while_op, Some(block));
+            }
+        }
+    }
+
+    fn compile_if(&mut self, op: &Op, if_op: &If, block: Option<usize>) {
+        let if_loc = &op.loc;
+        let mut exits = Vec::new();
+
+        let mut branch = self.emit(
+            Instr::Branch {
+                marker: Marker::If,
+                target: 0,
+            },
+            if_loc,
+            op.id,
+            block,
+        );
+        self.compile_block(&if_op.if_block, block);
+
+        for if_star in &if_op.if_star_blocks {
+            exits.push(self.emit(Instr::Jump(0), if_loc, op.id, block));
+            self.patch(branch);
+
+            self.compile_block(&if_star.cond, block);
+            branch = self.emit(
+                Instr::Branch {
+                    marker: Marker::IfStar,
+                    target: 0,
+                },
+                &if_star.loc,
+                op.id,
+                block,
+            );
+            self.compile_block(&if_star.inner, block);
+        }
+
+        if let Some(else_block) = &if_op.else_block {
+            exits.push(self.emit(Instr::Jump(0), if_loc, op.id, block));
+            self.patch(branch);
+
+            self.compile_block(else_block, block);
+        } else {
+            self.patch(branch);
+        }
+
+        for exit in exits {
+            self.patch(exit);
+        }
+    }
+
+    fn compile_while(&mut self, op: &Op, while_op: &While, block: Option<usize>) {
+        let while_loc = &op.loc;
+        let do_loc = while_op.do_loc.as_ref().unwrap_or(while_loc);
+
+        let start = self.instrs.len();
+        self.compile_block(&while_op.cond_block, block);
+
+        let branch = self.emit(
+            Instr::Branch {
+                marker: Marker::Do,
+                target: 0,
+            },
+            do_loc,
+            op.id,
+            block,
+        );
+        self.compile_block(&while_op.do_block, block);
+        self.emit(Instr::Jump(start), while_loc, op.id, block);
+
+        self.patch(branch);
+    }
+}
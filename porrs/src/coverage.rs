@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::op::{OpBlock, OpType, OpView};
+use crate::program::{FileLocation, Program};
+use crate::simulate::Observer;
+use crate::token::Marker;
+use crate::Result;
+
+/// An `if` or `while` whose arms are tracked
+#[derive(Debug)]
+struct BranchPoint {
+    loc: FileLocation,
+    evaluated: bool,
+    /// Times each arm was taken. For an `if` the arms are its own block, each
+    /// `if*` block and finally the `else` block, or falling through when there
+    /// is none. For a `while` they are entering the body and exiting the loop.
+    arms: Vec<u64>,
+}
+
+/// Where a condition leads to within its [`BranchPoint`]
+#[derive(Debug)]
+struct Condition {
+    point: usize,
+    arm: usize,
+    /// Whether the condition being false takes the last arm
+    last: bool,
+}
+
+/// An [`Observer`] recording which operations ran and which arms of each `if`
+/// and `while` were taken, to be written out as an lcov tracefile.
+#[derive(Debug)]
+pub struct Coverage {
+    ops: HashMap<FileLocation, u64>,
+    points: Vec<BranchPoint>,
+    conditions: HashMap<FileLocation, Condition>,
+}
+
+impl Coverage {
+    /// Prepares to record coverage of `program`, so that operations and arms
+    /// which never run are reported as well
+    pub fn new(program: &Program) -> Self {
+        let mut coverage = Self {
+            ops: HashMap::new(),
+            points: Vec::new(),
+            conditions: HashMap::new(),
+        };
+
+        coverage.add_block(&program.root_block);
+        coverage
+    }
+
+    fn add_block(&mut self, op_block: &OpBlock) {
+        for op in op_block.iter() {
+            self.ops.insert(op.loc.clone(), 0);
+
+            match &op.typ {
+                OpType::PushInt(_) | OpType::Intrinsic(_) | OpType::Native(..) => {}
+
+                OpType::If(if_op) => {
+                    let point = self.points.len();
+                    let arm_count = if_op.if_star_blocks.len() + 2;
+
+                    self.points.push(BranchPoint {
+                        loc: op.loc.clone(),
+                        evaluated: false,
+                        arms: vec![0; arm_count],
+                    });
+
+                    self.conditions.insert(
+                        op.loc.clone(),
+                        Condition {
+                            point,
+                            arm: 0,
+                            last: if_op.if_star_blocks.is_empty(),
+                        },
+                    );
+
+                    for (i, if_star) in if_op.if_star_blocks.iter().enumerate() {
+                        self.conditions.insert(
+                            if_star.loc.clone(),
+                            Condition {
+                                point,
+                                arm: i + 1,
+                                last: i + 1 == if_op.if_star_blocks.len(),
+                            },
+                        );
+                    }
+
+                    self.add_block(&if_op.if_block);
+                    for if_star in &if_op.if_star_blocks {
+                        self.add_block(&if_star.cond);
+                        self.add_block(&if_star.inner);
+                    }
+                    if let Some(else_block) = &if_op.else_block {
+                        self.add_block(else_block);
+                    }
+                }
+
+                OpType::While(while_op) => {
+                    let point = self.points.len();
+
+                    self.points.push(BranchPoint {
+                        loc: op.loc.clone(),
+                        evaluated: false,
+                        arms: vec![0; 2],
+                    });
+
+                    self.conditions.insert(
+                        while_op.do_loc.clone().unwrap_or_else(|| op.loc.clone()),
+                        Condition {
+                            point,
+                            arm: 0,
+                            last: true,
+                        },
+                    );
+
+                    self.add_block(&while_op.cond_block);
+                    self.add_block(&while_op.do_block);
+                }
+            }
+        }
+    }
+
+    /// Writes the coverage in the lcov tracefile format, with one record per
+    /// source file
+    pub fn write_lcov(&self, w: &mut impl Write) -> io::Result<()> {
+        #[derive(Default)]
+        struct FileRecord<'a> {
+            lines: BTreeMap<usize, u64>,
+            points: Vec<&'a BranchPoint>,
+        }
+
+        let mut files = BTreeMap::<PathBuf, FileRecord>::new();
+
+        for (loc, count) in &self.ops {
+            let line = match loc.line() {
+                Some(line) => line,
+                None => continue,
+            };
+
+            let hits = files
+                .entry(loc.path().to_path_buf())
+                .or_default()
+                .lines
+                .entry(line)
+                .or_default();
+            *hits = (*hits).max(*count);
+        }
+
+        for point in &self.points {
+            files
+                .entry(point.loc.path().to_path_buf())
+                .or_default()
+                .points
+                .push(point);
+        }
+
+        writeln!(w, "TN:")?;
+
+        for (path, record) in files {
+            writeln!(w, "SF:{}", path.display())?;
+
+            let mut branches_found = 0;
+            let mut branches_hit = 0;
+            for (block, point) in record.points.iter().enumerate() {
+                let line = point.loc.line().unwrap_or(0);
+
+                for (arm, count) in point.arms.iter().enumerate() {
+                    branches_found += 1;
+
+                    if point.evaluated {
+                        writeln!(w, "BRDA:{},{},{},{}", line, block, arm, count)?;
+                        if *count > 0 {
+                            branches_hit += 1;
+                        }
+                    } else {
+                        writeln!(w, "BRDA:{},{},{},-", line, block, arm)?;
+                    }
+                }
+            }
+            writeln!(w, "BRF:{}", branches_found)?;
+            writeln!(w, "BRH:{}", branches_hit)?;
+
+            for (line, hits) in &record.lines {
+                writeln!(w, "DA:{},{}", line, hits)?;
+            }
+            writeln!(w, "LF:{}", record.lines.len())?;
+            writeln!(
+                w,
+                "LH:{}",
+                record.lines.values().filter(|hits| **hits > 0).count()
+            )?;
+
+            writeln!(w, "end_of_record")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Observer for Coverage {
+    fn before_op(&mut self, op: &OpView<'_>, _stack: &[u64]) -> Result<()> {
+        if let Some(count) = self.ops.get_mut(op.location()) {
+            *count += 1;
+        }
+
+        Ok(())
+    }
+
+    fn on_branch(&mut self, _marker: Marker, loc: &FileLocation, taken: bool) -> Result<()> {
+        let condition = match self.conditions.get(loc) {
+            Some(condition) => condition,
+            None => return Ok(()),
+        };
+
+        let point = &mut self.points[condition.point];
+        point.evaluated = true;
+
+        if taken {
+            point.arms[condition.arm] += 1;
+        } else if condition.last {
+            *point.arms.last_mut().unwrap() += 1;
+        }
+
+        Ok(())
+    }
+}
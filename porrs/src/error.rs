@@ -2,37 +2,111 @@ use std::{error, fmt};
 
 use crate::lex::LexingError;
 use crate::parse::ParsingError;
+use crate::porbc::LoadingError;
 use crate::program::FileLocation;
 use crate::simulate::SimulationError;
 use crate::token::Marker;
 
+/// The stage of the pipeline an [`Error`] originated from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Category {
+    Lexing,
+    Parsing,
+    Loading,
+    Simulation,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lexing => write!(f, "Lexing"),
+            Self::Parsing => write!(f, "Parsing"),
+            Self::Loading => write!(f, "Loading"),
+            Self::Simulation => write!(f, "Simulation"),
+        }
+    }
+}
+
 #[derive(Debug)]
-enum ErrorKind {
+#[non_exhaustive]
+pub enum ErrorKind {
     Lexing(LexingError),
     Parsing(ParsingError),
+    Loading(LoadingError),
     Simulation(SimulationError),
 }
 
+impl ErrorKind {
+    pub fn category(&self) -> Category {
+        match self {
+            Self::Lexing(_) => Category::Lexing,
+            Self::Parsing(_) => Category::Parsing,
+            Self::Loading(_) => Category::Loading,
+            Self::Simulation(_) => Category::Simulation,
+        }
+    }
+}
+
 impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use ErrorKind::*;
         match self {
-            Lexing(err) => write!(f, "[Lexing] {}", err),
-            Parsing(err) => write!(f, "[Parsing] {}", err),
-            Simulation(err) => write!(f, "[Simulation] {}", err),
+            Lexing(err) => write!(f, "{}", err),
+            Parsing(err) => write!(f, "{}", err),
+            Loading(err) => write!(f, "{}", err),
+            Simulation(err) => write!(f, "{}", err),
         }
     }
 }
 
+/// Additional context attached to an [`Error`]
 #[derive(Debug)]
-pub(crate) enum InfoKind {
+#[non_exhaustive]
+pub enum InfoKind {
     BlockStart(Marker),
+    InBlock(Marker),
+    /// The `top` values of the stack (top last) out of `len` values in total
+    StackSnapshot {
+        top: Vec<u64>,
+        len: usize,
+    },
+    Suggestion(Vec<String>),
+    Hint(&'static str),
+    /// The operation which pushed a value left on the stack
+    PushedHere(u64),
 }
 
 impl fmt::Display for InfoKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::BlockStart(marker) => write!(f, "`{}` block starts here", marker),
+
+            Self::InBlock(marker) => write!(f, "inside the `{}` block starting here", marker),
+
+            Self::StackSnapshot { top, len } => {
+                write!(f, "stack at the time of failure")?;
+                if *len > top.len() {
+                    write!(f, " (top {} of {} values)", top.len(), len)?;
+                }
+                write!(f, ": {:?}", top)
+            }
+
+            Self::Suggestion(words) => match words.as_slice() {
+                [word] => write!(f, "did you mean `{}`?", word),
+                words => {
+                    let words = words
+                        .iter()
+                        .map(|word| format!("`{}`", word))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    write!(f, "did you mean one of {}?", words)
+                }
+            },
+
+            Self::Hint(hint) => write!(f, "hint: {}", hint),
+
+            Self::PushedHere(val) => write!(f, "`{}` was pushed here", val),
         }
     }
 }
@@ -43,6 +117,16 @@ pub struct Info {
     loc: FileLocation,
 }
 
+impl Info {
+    pub fn kind(&self) -> &InfoKind {
+        &self.kind
+    }
+
+    pub fn location(&self) -> &FileLocation {
+        &self.loc
+    }
+}
+
 impl fmt::Display for Info {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "<-- {} --> {}", self.loc, self.kind)
@@ -54,17 +138,31 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
-    loc: Option<FileLocation>,
+    loc: Option<Box<FileLocation>>,
     info_stack: Vec<Info>,
 }
 
 impl Error {
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn category(&self) -> Category {
+        self.kind.category()
+    }
+
+    /// Location in the source that caused the error, if there is one
+    pub fn location(&self) -> Option<&FileLocation> {
+        self.loc.as_deref()
+    }
+
+    /// Notes giving further context to the error, innermost first
     pub fn info_stack(&self) -> &[Info] {
         &self.info_stack
     }
 
     pub(crate) fn add_loc(mut self, loc: FileLocation) -> Self {
-        self.loc = Some(loc);
+        self.loc = Some(Box::new(loc));
         self
     }
 
@@ -106,14 +204,29 @@ impl From<ParsingError> for Error {
     }
 }
 
+impl From<LoadingError> for Error {
+    fn from(err: LoadingError) -> Self {
+        Self::from(ErrorKind::Loading(err))
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if let Some(loc) = &self.loc {
             write!(f, "<-- {} --> ", loc)?;
         }
 
-        write!(f, "{}", self.kind)
+        write!(f, "[{}] {}", self.category(), self.kind)
     }
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Lexing(err) => err.source(),
+            ErrorKind::Loading(err) => err.source(),
+            ErrorKind::Simulation(err) => err.source(),
+            ErrorKind::Parsing(_) => None,
+        }
+    }
+}
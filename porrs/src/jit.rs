@@ -0,0 +1,422 @@
+use std::io;
+use std::ptr::{self, NonNull};
+
+use crate::bytecode::{Addr, Bytecode, Instr};
+use crate::op::Intrinsic;
+use crate::simulate::Stack;
+use crate::Result;
+
+/// State shared with the machine code, which keeps the stack in registers
+/// while running and writes it back whenever it returns
+#[repr(C)]
+struct JitState {
+    base: *mut u64,
+    len: usize,
+    cap: usize,
+    /// Instruction the code trapped on
+    pc: usize,
+}
+
+const STATE_LEN: u8 = 8;
+const STATE_PC: u8 = 24;
+
+/// Returned by the machine code once it runs past the last instruction
+const STATUS_DONE: u64 = 0;
+/// Returned by the machine code when an instruction has to be executed by the
+/// interpreter
+const STATUS_TRAP: u64 = 1;
+
+type Entry = unsafe extern "sysv64" fn(state: *mut JitState, code: *const u8) -> u64;
+
+/// [`Bytecode`] translated to x86-64 machine code, run through
+/// [`Vm::with_jit`](crate::Vm::with_jit).
+///
+/// The data stack is kept in registers while the code runs. Instructions which
+/// cannot be translated, like `print` and native words, and any instruction
+/// which would underflow the stack, outgrow its allocation, overflow, divide
+/// by zero or branch on a value other than 0 or 1 trap back into the
+/// interpreter. It executes just that instruction, so output, warnings and
+/// errors are exactly those of the [`Vm`](crate::Vm), including how overflows
+/// are handled under its [`OverflowPolicy`](crate::OverflowPolicy), and the
+/// code resumes right after it.
+///
+/// Only available on x86-64 Linux.
+pub struct Jit<'a> {
+    bytecode: &'a Bytecode,
+    code: ExecutableBuffer,
+    /// Offset of the code of each instruction, followed by the offset of the
+    /// end of the program
+    offsets: Vec<usize>,
+}
+
+impl<'a> Jit<'a> {
+    /// Translates `bytecode` and maps it into executable memory
+    pub fn compile(bytecode: &'a Bytecode) -> io::Result<Self> {
+        let mut asm = Assembler::default();
+        asm.compile(bytecode);
+
+        let code = ExecutableBuffer::new(&asm.buf)?;
+
+        log::debug!(
+            "Compiled {} instructions to {} bytes of machine code",
+            bytecode.len(),
+            asm.buf.len()
+        );
+
+        Ok(Self {
+            bytecode,
+            code,
+            offsets: asm.offsets,
+        })
+    }
+
+    pub(crate) fn bytecode(&self) -> &'a Bytecode {
+        self.bytecode
+    }
+
+    /// Runs the code on `stack` from the instruction `pc`, calling `step` to
+    /// interpret any instruction it traps on
+    pub(crate) fn execute(
+        &self,
+        stack: &mut Stack,
+        mut pc: Addr,
+        mut step: impl FnMut(&mut Stack, Addr) -> Result<Addr>,
+    ) -> Result<()> {
+        // SAFETY: the buffer starts with the prologue, which follows the
+        // `Entry` signature
+        let entry: Entry = unsafe { std::mem::transmute(self.code.ptr.as_ptr()) };
+
+        loop {
+            let vec = &mut stack.0;
+            let mut state = JitState {
+                base: vec.as_mut_ptr(),
+                len: vec.len(),
+                cap: vec.capacity(),
+                pc,
+            };
+
+            // SAFETY: the code only reads below `len` and only writes below
+            // `cap`, and every value below the returned `len` was written
+            let status = unsafe {
+                let status = entry(&mut state, self.code.ptr.as_ptr().add(self.offsets[pc]));
+                vec.set_len(state.len);
+                status
+            };
+
+            match status {
+                STATUS_DONE => return Ok(()),
+                STATUS_TRAP => pc = step(stack, state.pc)?,
+                _ => unreachable!("JIT code returned unknown status {}", status),
+            }
+        }
+    }
+}
+
+/// Memory mapped read and execute only, holding a copy of some code
+struct ExecutableBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl ExecutableBuffer {
+    fn new(code: &[u8]) -> io::Result<Self> {
+        let len = code.len().max(1);
+
+        // SAFETY: a fresh anonymous mapping is only accessed within `len`
+        unsafe {
+            let ptr = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            let buffer = Self {
+                ptr: NonNull::new_unchecked(ptr.cast()),
+                len,
+            };
+
+            ptr::copy_nonoverlapping(code.as_ptr(), buffer.ptr.as_ptr(), code.len());
+
+            if libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(buffer)
+        }
+    }
+}
+
+impl Drop for ExecutableBuffer {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created in `new` and is no longer referenced
+        unsafe {
+            libc::munmap(self.ptr.as_ptr().cast(), self.len);
+        }
+    }
+}
+
+/// Registers holding the base and length of the stack while the code runs.
+/// Its capacity is kept in r13 and the state pointer in r14.
+const BASE: u8 = 3; // rbx
+const LEN: u8 = 12; // r12
+
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+
+const JB: u8 = 0x82;
+const JAE: u8 = 0x83;
+const JZ: u8 = 0x84;
+const JA: u8 = 0x87;
+
+/// Emits x86-64 machine code for [`Bytecode`]
+#[derive(Default)]
+struct Assembler {
+    buf: Vec<u8>,
+    offsets: Vec<usize>,
+    /// Jumps to be pointed at the code of an instruction, as the position of
+    /// their rel32 and the instruction
+    jumps: Vec<(usize, Addr)>,
+    /// Conditional jumps to be pointed at a stub trapping on an instruction
+    traps: Vec<(usize, Addr)>,
+}
+
+impl Assembler {
+    fn compile(&mut self, bytecode: &Bytecode) {
+        self.prologue();
+
+        for (pc, instr) in bytecode.instrs.iter().enumerate() {
+            self.offsets.push(self.buf.len());
+            self.instr(pc, *instr);
+        }
+
+        self.offsets.push(self.buf.len());
+        self.exit(STATUS_DONE);
+
+        for (at, pc) in std::mem::take(&mut self.jumps) {
+            let target = self.offsets[pc];
+            self.patch(at, target);
+        }
+
+        for (at, pc) in std::mem::take(&mut self.traps) {
+            let stub = self.buf.len();
+            self.patch(at, stub);
+
+            // mov qword [r14 + pc], imm32
+            self.buf.extend([0x49, 0xC7, 0x46, STATE_PC]);
+            self.buf.extend((pc as u32).to_le_bytes());
+            self.exit(STATUS_TRAP);
+        }
+    }
+
+    fn instr(&mut self, pc: Addr, instr: Instr) {
+        match instr {
+            Instr::PushInt(val) => {
+                self.require_room(pc);
+                // mov rax, imm64
+                self.buf.extend([0x48, 0xB8]);
+                self.buf.extend(val.to_le_bytes());
+                self.store(RAX, 0);
+                self.inc_len();
+            }
+
+            Instr::Intrinsic(intr) => self.intrinsic(pc, intr),
+
+            Instr::Native(_) => self.trap(pc),
+
+            Instr::Branch { target, .. } => {
+                self.require(pc, 1);
+                self.load(RAX, -1);
+                // cmp rax, 1
+                self.buf.extend([0x48, 0x83, 0xF8, 0x01]);
+                self.jcc_trap(JA, pc);
+                self.dec_len();
+                // test rax, rax
+                self.buf.extend([0x48, 0x85, 0xC0]);
+                self.jcc(JZ, target);
+            }
+
+            Instr::Jump(target) => {
+                self.buf.push(0xE9);
+                self.jumps.push((self.buf.len(), target));
+                self.rel32();
+            }
+        }
+    }
+
+    fn intrinsic(&mut self, pc: Addr, intr: Intrinsic) {
+        match intr {
+            Intrinsic::Dup => {
+                self.require(pc, 1);
+                self.require_room(pc);
+                self.load(RAX, -1);
+                self.store(RAX, 0);
+                self.inc_len();
+            }
+
+            Intrinsic::Swap => {
+                self.require(pc, 2);
+                self.load(RAX, -1);
+                self.load(RCX, -2);
+                self.store(RCX, -1);
+                self.store(RAX, -2);
+            }
+
+            Intrinsic::Drop => {
+                self.require(pc, 1);
+                self.dec_len();
+            }
+
+            Intrinsic::Print | Intrinsic::Exit => self.trap(pc),
+
+            Intrinsic::Over => {
+                self.require(pc, 2);
+                self.require_room(pc);
+                self.load(RAX, -2);
+                self.store(RAX, 0);
+                self.inc_len();
+            }
+
+            Intrinsic::Rot => {
+                self.require(pc, 3);
+                self.load(RAX, -3);
+                self.load(RCX, -2);
+                self.load(RDX, -1);
+                self.store(RCX, -3);
+                self.store(RDX, -2);
+                self.store(RAX, -1);
+            }
+
+            Intrinsic::Plus | Intrinsic::Subtract | Intrinsic::Multiply => {
+                self.require(pc, 2);
+                self.load(RAX, -2);
+                match intr {
+                    // add rax, [slot]
+                    Intrinsic::Plus => self.slot_op(&[0x03], RAX, -1),
+                    // sub rax, [slot]
+                    Intrinsic::Subtract => self.slot_op(&[0x2B], RAX, -1),
+                    // mul qword [slot]
+                    _ => self.slot_op(&[0xF7], 4, -1),
+                }
+                // Overflows are reported by the interpreter
+                self.jcc_trap(JB, pc);
+                self.store(RAX, -2);
+                self.dec_len();
+            }
+
+            Intrinsic::DivMod => {
+                self.require(pc, 2);
+                self.load(RCX, -1);
+                // test rcx, rcx
+                self.buf.extend([0x48, 0x85, 0xC9]);
+                self.jcc_trap(JZ, pc);
+                self.load(RAX, -2);
+                // xor edx, edx; div rcx
+                self.buf.extend([0x31, 0xD2, 0x48, 0xF7, 0xF1]);
+                self.store(RAX, -2);
+                self.store(RDX, -1);
+            }
+        }
+    }
+
+    fn prologue(&mut self) {
+        // push rbx; push r12; push r13; push r14
+        self.buf.extend([0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56]);
+        // mov r14, rdi
+        self.buf.extend([0x49, 0x89, 0xFE]);
+        // mov rbx, [r14]; mov r12, [r14 + 8]; mov r13, [r14 + 16]
+        self.buf.extend([0x49, 0x8B, 0x5E, 0x00]);
+        self.buf.extend([0x4D, 0x8B, 0x66, 0x08]);
+        self.buf.extend([0x4D, 0x8B, 0x6E, 0x10]);
+        // jmp rsi
+        self.buf.extend([0xFF, 0xE6]);
+    }
+
+    /// Writes the stack length back and returns `status`
+    fn exit(&mut self, status: u64) {
+        // mov [r14 + len], r12
+        self.buf.extend([0x4D, 0x89, 0x66, STATE_LEN]);
+        // mov eax, imm32
+        self.buf.push(0xB8);
+        self.buf.extend((status as u32).to_le_bytes());
+        // pop r14; pop r13; pop r12; pop rbx; ret
+        self.buf.extend([0x41, 0x5E, 0x41, 0x5D, 0x41, 0x5C, 0x5B, 0xC3]);
+    }
+
+    /// Traps unless the stack holds at least `needed` values
+    fn require(&mut self, pc: Addr, needed: u8) {
+        // cmp r12, imm8
+        self.buf.extend([0x49, 0x83, 0xFC, needed]);
+        self.jcc_trap(JB, pc);
+    }
+
+    /// Traps unless the stack has room for one more value
+    fn require_room(&mut self, pc: Addr) {
+        // cmp r12, r13
+        self.buf.extend([0x4D, 0x39, 0xEC]);
+        self.jcc_trap(JAE, pc);
+    }
+
+    fn trap(&mut self, pc: Addr) {
+        self.buf.push(0xE9);
+        self.traps.push((self.buf.len(), pc));
+        self.rel32();
+    }
+
+    fn jcc_trap(&mut self, cond: u8, pc: Addr) {
+        self.buf.extend([0x0F, cond]);
+        self.traps.push((self.buf.len(), pc));
+        self.rel32();
+    }
+
+    fn jcc(&mut self, cond: u8, target: Addr) {
+        self.buf.extend([0x0F, cond]);
+        self.jumps.push((self.buf.len(), target));
+        self.rel32();
+    }
+
+    fn rel32(&mut self) {
+        self.buf.extend([0; 4]);
+    }
+
+    /// Points the rel32 at `at` to the code at `target`
+    fn patch(&mut self, at: usize, target: usize) {
+        let rel = target as i64 - (at as i64 + 4);
+        self.buf[at..at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+    }
+
+    /// Emits `opcode` with `reg` and the stack slot `slot` relative to its
+    /// length, i.e. `[rbx + r12 * 8 + slot * 8]`
+    fn slot_op(&mut self, opcode: &[u8], reg: u8, slot: i8) {
+        let rex = 0x48 | ((reg >> 3) << 2) | ((LEN >> 3) << 1) | (BASE >> 3);
+        self.buf.push(rex);
+        self.buf.extend(opcode);
+        self.buf.push(0x44 | ((reg & 7) << 3));
+        self.buf.push(0xC0 | ((LEN & 7) << 3) | (BASE & 7));
+        self.buf.push((slot * 8) as u8);
+    }
+
+    fn load(&mut self, reg: u8, slot: i8) {
+        self.slot_op(&[0x8B], reg, slot);
+    }
+
+    fn store(&mut self, reg: u8, slot: i8) {
+        self.slot_op(&[0x89], reg, slot);
+    }
+
+    fn inc_len(&mut self) {
+        // inc r12
+        self.buf.extend([0x49, 0xFF, 0xC4]);
+    }
+
+    fn dec_len(&mut self) {
+        // dec r12
+        self.buf.extend([0x49, 0xFF, 0xCC]);
+    }
+}
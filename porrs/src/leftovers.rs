@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::error::InfoKind;
+use crate::native::Natives;
+use crate::op::{Intrinsic, OpBlock, OpId, OpKind, OpType, OpView};
+use crate::program::{FileLocation, Program};
+use crate::simulate::{Observer, SimulationError};
+use crate::token::Marker;
+use crate::{Error, Result};
+
+/// Number of leftover values, from the top, whose origin is noted
+const LEFTOVER_NOTES: usize = 8;
+
+/// An [`Observer`] following which operation pushed each value on the stack,
+/// to report the values a program leaves behind when it finishes.
+///
+/// ```
+/// # fn main() -> porrs::Result<()> {
+/// let program = porrs::Program::from_source("<example>", "1 2 3 + drop 4")?;
+///
+/// let mut leftovers = porrs::Leftovers::new(&program);
+/// let outcome = porrs::Simulator::new(&program)
+///     .with_observer(&mut leftovers)
+///     .run()?;
+///
+/// let err = leftovers.check(&outcome.stack).unwrap_err();
+/// assert_eq!(err.info_stack().len(), 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Leftovers {
+    natives: Arc<Natives>,
+    /// Location of every operation in the program. Only the id is kept per
+    /// value, as cloning the location on every push would slow the
+    /// simulation down considerably.
+    locs: HashMap<OpId, FileLocation>,
+    /// Operation which pushed each value, top last
+    origins: Vec<Option<OpId>>,
+}
+
+impl Leftovers {
+    pub fn new(program: &Program) -> Self {
+        let mut leftovers = Self {
+            natives: Arc::clone(&program.natives),
+            locs: HashMap::new(),
+            origins: Vec::new(),
+        };
+
+        leftovers.add_block(&program.root_block);
+        leftovers
+    }
+
+    fn add_block(&mut self, op_block: &OpBlock) {
+        for op in op_block.iter() {
+            self.locs.insert(op.id, op.loc.clone());
+
+            match &op.typ {
+                OpType::PushInt(_) | OpType::Intrinsic(_) | OpType::Native(..) => {}
+
+                OpType::If(if_op) => {
+                    self.add_block(&if_op.if_block);
+                    for if_star in &if_op.if_star_blocks {
+                        self.add_block(&if_star.cond);
+                        self.add_block(&if_star.inner);
+                    }
+                    if let Some(else_block) = &if_op.else_block {
+                        self.add_block(else_block);
+                    }
+                }
+
+                OpType::While(while_op) => {
+                    self.add_block(&while_op.cond_block);
+                    self.add_block(&while_op.do_block);
+                }
+            }
+        }
+    }
+
+    /// Fails with [`SimulationError::LeftoverValues`] unless `stack` is
+    /// empty, noting where the top values were pushed, top first. Values
+    /// pushed where this observer could not see them, e.g. when the program
+    /// ran on a [`Vm`](crate::Vm), are not noted.
+    pub fn check(&self, stack: &[u64]) -> Result<()> {
+        if stack.is_empty() {
+            return Ok(());
+        }
+
+        let known = self.origins.len().min(stack.len());
+        let origins = &self.origins[self.origins.len() - known..];
+        let vals = &stack[stack.len() - known..];
+
+        let err = vals
+            .iter()
+            .zip(origins)
+            .rev()
+            .filter_map(|(val, origin)| Some((*val, self.locs.get(origin.as_ref()?)?)))
+            .take(LEFTOVER_NOTES)
+            .fold(
+                Error::from(SimulationError::LeftoverValues(stack.len())),
+                |err, (val, loc)| err.push_info(InfoKind::PushedHere(val), loc.clone()),
+            );
+
+        Err(err)
+    }
+
+    /// Keeps one origin per value on `stack`, for values pushed before the
+    /// observer was attached
+    fn sync(&mut self, len: usize) {
+        self.origins.resize(len, None);
+    }
+}
+
+impl Observer for Leftovers {
+    fn after_op(&mut self, op: &OpView<'_>, stack: &[u64]) -> Result<()> {
+        let origins = &mut self.origins;
+        let here = || Some(op.id());
+
+        match op.kind() {
+            OpKind::PushInt(_) => origins.push(here()),
+
+            OpKind::Intrinsic(intr) => {
+                let inputs = intr.arity().inputs;
+                if origins.len() < inputs {
+                    self.sync(stack.len());
+                    return Ok(());
+                }
+
+                let top = origins.len() - 1;
+                match intr {
+                    Intrinsic::Dup => origins.push(origins[top]),
+                    Intrinsic::Over => origins.push(origins[top - 1]),
+                    Intrinsic::Swap => origins.swap(top, top - 1),
+                    Intrinsic::Rot => origins[top - 2..].rotate_left(1),
+                    Intrinsic::Drop | Intrinsic::Print | Intrinsic::Exit => {
+                        origins.pop();
+                    }
+                    Intrinsic::Plus | Intrinsic::Subtract | Intrinsic::Multiply => {
+                        origins.truncate(top - 1);
+                        origins.push(here());
+                    }
+                    Intrinsic::DivMod => {
+                        origins.truncate(top - 1);
+                        origins.push(here());
+                        origins.push(here());
+                    }
+                }
+            }
+
+            OpKind::Native(name) => {
+                let inputs = self
+                    .natives
+                    .find(name)
+                    .map_or(0, |id| self.natives.get(id).arity.inputs);
+                let base = origins.len().saturating_sub(inputs).min(stack.len());
+
+                origins.truncate(base);
+                origins.resize_with(stack.len(), here);
+            }
+
+            OpKind::If | OpKind::While | OpKind::Branch(_) | OpKind::Jump => {}
+        }
+
+        self.sync(stack.len());
+        Ok(())
+    }
+
+    fn on_branch(&mut self, _marker: Marker, _loc: &FileLocation, _taken: bool) -> Result<()> {
+        self.origins.pop();
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{error, fmt};
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 use std::path::{Path, PathBuf};
@@ -8,7 +8,8 @@ use crate::token::{Token, TokenType};
 use crate::{Error, Result};
 
 #[derive(Debug)]
-pub(crate) enum LexingError {
+#[non_exhaustive]
+pub enum LexingError {
     FileIo(PathBuf, io::Error),
 }
 
@@ -20,8 +21,16 @@ impl fmt::Display for LexingError {
     }
 }
 
+impl error::Error for LexingError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::FileIo(_, err) => Some(err),
+        }
+    }
+}
+
 pub(crate) struct Lexer {
-    file_reader: BufReader<File>,
+    reader: Box<dyn BufRead>,
     current_location: FileLocation,
     lexing_line: String,
 }
@@ -40,11 +49,17 @@ impl Lexer {
 
         log::debug!("Opened file: {}", path.as_ref().display());
 
-        Ok(Lexer {
-            file_reader,
+        Ok(Self::from_reader(path, file_reader))
+    }
+
+    /// Lexes the text read from `reader`, reporting locations as if it is the
+    /// content of the file at `path`
+    pub(crate) fn from_reader(path: impl AsRef<Path>, reader: impl BufRead + 'static) -> Self {
+        Lexer {
+            reader: Box::new(reader),
             current_location: FileLocation::from_path(path),
             lexing_line: "".to_owned(),
-        })
+        }
     }
 
     pub(crate) fn consume_token(&mut self) -> Result<Option<Token>> {
@@ -53,10 +68,8 @@ impl Lexer {
             let mut text = "".to_owned();
 
             while text.is_empty() {
-                if self.lexing_line.is_empty() {
-                    if let None = self.consume_line()? {
-                        return Ok(None);
-                    }
+                if self.lexing_line.is_empty() && self.consume_line()?.is_none() {
+                    return Ok(None);
                 }
 
                 if self.lexing_line.starts_with("//") {
@@ -80,6 +93,10 @@ impl Lexer {
                     initial_len - self.lexing_line.len();
             }
 
+            if let Some(pos) = loc.pos.as_mut() {
+                pos.len = text.len();
+            }
+
             (text, loc)
         };
 
@@ -97,7 +114,7 @@ impl Lexer {
     }
 
     fn consume_line(&mut self) -> Result<Option<()>> {
-        match self.file_reader.read_line(&mut self.lexing_line) {
+        match self.reader.read_line(&mut self.lexing_line) {
             Ok(0) => return Ok(None),
             Ok(bytes) => {
                 match self.current_location.pos.as_mut() {
@@ -105,7 +122,13 @@ impl Lexer {
                         pos.line += 1;
                         pos.col = 1;
                     }
-                    None => self.current_location.pos = Some(FilePosition { line: 1, col: 1 }),
+                    None => {
+                        self.current_location.pos = Some(FilePosition {
+                            line: 1,
+                            col: 1,
+                            len: 0,
+                        })
+                    }
                 }
 
                 log::debug!(
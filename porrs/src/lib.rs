@@ -1,15 +1,48 @@
-#![feature(const_mut_refs)]
-
+mod bytecode;
+mod coverage;
 mod error;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod jit;
+mod leftovers;
 mod lex;
+mod limits;
+mod native;
 mod op;
 mod parse;
+mod porbc;
+mod profile;
 mod program;
+mod replay;
 mod simulate;
+mod snapshot;
+mod suggest;
 mod token;
+mod trace;
+mod vm;
+
+pub use coverage::Coverage;
+pub use error::{Category, Error, ErrorKind, Info, InfoKind, Result};
+pub use lex::LexingError;
+pub use parse::{MissingMarker, ParsingError, UnexpectedMarker};
+pub use porbc::LoadingError;
+pub use simulate::SimulationError;
+pub use token::Marker;
+
+pub use native::{NativeContext, NativeError, Natives, RegistrationError};
+pub use op::{Arity, Intrinsic, OpId, OpKind, OpView};
+
+pub use leftovers::Leftovers;
+pub use limits::Limits;
+pub use profile::{ProfileEntry, Profiler};
+pub use program::{FileLocation, FilePosition, Program};
+pub use simulate::{simulate, simulate_with, Observer, Outcome, OverflowPolicy, Simulator};
+pub use trace::{Tracer, TRACE_STACK_TOP, TRACE_VERSION};
 
-pub use error::Error;
-pub use error::Result;
+pub use bytecode::Bytecode;
+pub use porbc::{PORBC_MAGIC, PORBC_VERSION};
+pub use replay::{Divergence, Recording, RECORDING_MAGIC, RECORDING_VERSION};
+pub use snapshot::{Snapshot, SNAPSHOT_MAGIC, SNAPSHOT_VERSION};
+pub use vm::Vm;
 
-pub use program::Program;
-pub use simulate::simulate;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub use jit::Jit;
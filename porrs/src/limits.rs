@@ -0,0 +1,117 @@
+use std::mem;
+use std::time::{Duration, Instant};
+
+use crate::op::OpView;
+use crate::simulate::{Observer, SimulationError};
+use crate::{Error, Result};
+
+/// Number of operations executed between checks of the wall clock
+const CLOCK_CHECK_INTERVAL: u64 = 256;
+
+/// An [`Observer`] stopping the simulation once any of the configured limits is
+/// exceeded, with an error located at the offending operation.
+///
+/// A [`Vm`](crate::Vm) checks the same limits when given them with
+/// [`with_limits`](crate::Vm::with_limits), counting instructions rather than
+/// operations.
+#[derive(Debug, Default)]
+pub struct Limits {
+    max_ops: Option<u64>,
+    max_stack_depth: Option<usize>,
+    max_memory: Option<usize>,
+    timeout: Option<Duration>,
+    ops: u64,
+    started: Option<Instant>,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the number of operations executed, counting `if` and `while`
+    /// themselves as well as every operation within their blocks
+    pub fn max_ops(mut self, max_ops: u64) -> Self {
+        self.max_ops = Some(max_ops);
+        self
+    }
+
+    /// Limits the number of values the stack can hold
+    pub fn max_stack_depth(mut self, max_stack_depth: usize) -> Self {
+        self.max_stack_depth = Some(max_stack_depth);
+        self
+    }
+
+    /// Limits the number of bytes the program uses. The data stack is the
+    /// only storage a program can grow, each value taking 8 bytes.
+    pub fn max_memory(mut self, max_memory: usize) -> Self {
+        self.max_memory = Some(max_memory);
+        self
+    }
+
+    /// Limits the wall-clock time, measured from the first operation
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Number of operations executed so far
+    pub fn ops(&self) -> u64 {
+        self.ops
+    }
+}
+
+impl Limits {
+    /// Counts an operation about to run, failing if it is over the limit or
+    /// the time ran out
+    pub(crate) fn check_before(&mut self) -> Result<()> {
+        self.ops += 1;
+
+        if let Some(max_ops) = self.max_ops {
+            if self.ops > max_ops {
+                return Err(Error::from(SimulationError::OpLimitExceeded(max_ops)));
+            }
+        }
+
+        if let Some(timeout) = self.timeout {
+            let started = *self.started.get_or_insert_with(Instant::now);
+
+            if self.ops.is_multiple_of(CLOCK_CHECK_INTERVAL) && started.elapsed() > timeout {
+                return Err(Error::from(SimulationError::TimedOut(timeout)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the stack an operation left behind
+    pub(crate) fn check_after(&self, stack: &[u64]) -> Result<()> {
+        if let Some(max_stack_depth) = self.max_stack_depth {
+            if stack.len() > max_stack_depth {
+                return Err(Error::from(SimulationError::StackLimitExceeded(
+                    max_stack_depth,
+                )));
+            }
+        }
+
+        if let Some(max_memory) = self.max_memory {
+            if mem::size_of_val(stack) > max_memory {
+                return Err(Error::from(SimulationError::MemoryLimitExceeded(
+                    max_memory,
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Observer for Limits {
+    fn before_op(&mut self, _op: &OpView<'_>, _stack: &[u64]) -> Result<()> {
+        self.check_before()
+    }
+
+    fn after_op(&mut self, _op: &OpView<'_>, stack: &[u64]) -> Result<()> {
+        self.check_after(stack)
+    }
+}
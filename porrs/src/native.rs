@@ -0,0 +1,192 @@
+use std::error;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+use crate::op::{Arity, Intrinsic};
+use crate::simulate::SimulationError;
+use crate::token::TokenType;
+use crate::{Error, Result};
+
+/// Error returned by a native function, reported as a [`SimulationError`]
+pub type NativeError = Box<dyn error::Error + Send + Sync>;
+
+type NativeFn =
+    dyn Fn(&mut NativeContext<'_>) -> std::result::Result<(), NativeError> + Send + Sync;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct NativeId(usize);
+
+/// Reason [`Natives::register`] refused a name
+#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RegistrationError {
+    /// The name is empty or contains whitespace
+    NotAWord(String),
+    /// The name is an integer, an [`Intrinsic`] or a [`Marker`](crate::Marker)
+    Reserved(String),
+    AlreadyRegistered(String),
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAWord(name) => write!(f, "Native name `{}` is not a single word", name),
+            Self::Reserved(name) => {
+                write!(f, "Native name `{}` is reserved by the language", name)
+            }
+            Self::AlreadyRegistered(name) => {
+                write!(f, "Native `{}` is already registered", name)
+            }
+        }
+    }
+}
+
+impl error::Error for RegistrationError {}
+
+pub(crate) struct Native {
+    pub(crate) name: String,
+    pub(crate) arity: Arity,
+    pub(crate) func: Box<NativeFn>,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Native")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Words defined by the host embedding the simulator, implemented in Rust.
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::sync::Arc;
+///
+/// use porrs::{Arity, Natives, Program, Simulator};
+///
+/// let mut natives = Natives::new();
+/// natives.register("square", Arity::new(1, 1), |ctx| {
+///     let val = ctx.pop()?;
+///     ctx.push(val * val);
+///     Ok(())
+/// })?;
+///
+/// let program = Program::from_source_with("<example>", "7 square", Arc::new(natives))?;
+/// assert_eq!(Simulator::new(&program).run()?.stack, [49]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct Natives(Vec<Native>);
+
+impl Natives {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `name` available to programs as a word taking and leaving the
+    /// number of values declared by `arity`. The simulator checks that enough
+    /// values are on the stack before calling `func`, and that `func` leaves
+    /// exactly as many as declared.
+    ///
+    /// Fails if `name` is not a valid word, i.e. it contains whitespace or is
+    /// an integer, an [`Intrinsic`] or a [`Marker`](crate::Marker), or if it is
+    /// already registered.
+    ///
+    /// ```
+    /// use porrs::{Arity, Natives, RegistrationError};
+    ///
+    /// let mut natives = Natives::new();
+    /// let err = natives.register("dup", Arity::new(1, 2), |_| Ok(())).unwrap_err();
+    /// assert_eq!(err, RegistrationError::Reserved("dup".to_owned()));
+    /// ```
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        arity: Arity,
+        func: impl Fn(&mut NativeContext<'_>) -> std::result::Result<(), NativeError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> std::result::Result<&mut Self, RegistrationError> {
+        let name = name.into();
+
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(RegistrationError::NotAWord(name));
+        }
+        if !matches!(TokenType::from(&name), TokenType::Word(_))
+            || Intrinsic::try_from(name.as_str()).is_ok()
+        {
+            return Err(RegistrationError::Reserved(name));
+        }
+        if self.find(&name).is_some() {
+            return Err(RegistrationError::AlreadyRegistered(name));
+        }
+
+        self.0.push(Native {
+            name,
+            arity,
+            func: Box::new(func),
+        });
+        Ok(self)
+    }
+
+    /// Names of every registered native
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|native| native.name.as_str())
+    }
+
+    pub(crate) fn find(&self, name: &str) -> Option<NativeId> {
+        self.0
+            .iter()
+            .position(|native| native.name == name)
+            .map(NativeId)
+    }
+
+    pub(crate) fn get(&self, id: NativeId) -> &Native {
+        &self.0[id.0]
+    }
+}
+
+/// Access to the state of the simulation given to native functions.
+///
+/// The data stack is the only state a program has, besides the input and
+/// output streams of the [`Simulator`](crate::Simulator).
+pub struct NativeContext<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) stack: &'a mut Vec<u64>,
+    pub(crate) output: &'a mut dyn Write,
+    pub(crate) input: &'a mut dyn BufRead,
+}
+
+impl NativeContext<'_> {
+    /// Pops the top value off the stack
+    pub fn pop(&mut self) -> Result<u64> {
+        self.stack.pop().ok_or_else(|| {
+            Error::from(SimulationError::StackUnderflow {
+                word: self.name.to_owned(),
+                needed: 1,
+                available: 0,
+            })
+        })
+    }
+
+    pub fn push(&mut self, val: u64) {
+        self.stack.push(val)
+    }
+
+    /// The whole stack, top last
+    pub fn stack(&mut self) -> &mut Vec<u64> {
+        self.stack
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        self.output
+    }
+
+    pub fn input(&mut self) -> &mut dyn BufRead {
+        self.input
+    }
+}
@@ -1,6 +1,8 @@
 use std::fmt;
 
+use crate::native::NativeId;
 use crate::program::FileLocation;
+use crate::token::Marker;
 
 #[derive(Debug)]
 pub(crate) struct OpBlock(Vec<Op>);
@@ -21,20 +23,126 @@ impl OpBlock {
 
 #[derive(Debug)]
 pub struct Op {
+    pub(crate) id: OpId,
     pub(crate) typ: OpType,
     pub(crate) loc: FileLocation,
 }
 
+/// Identifies an operation within its [`Program`](crate::Program), numbered in
+/// the order the operations appear in the source
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OpId(pub(crate) u32);
+
+impl Op {
+    pub(crate) fn kind(&self) -> OpKind<'_> {
+        match &self.typ {
+            OpType::PushInt(val) => OpKind::PushInt(*val),
+            OpType::Intrinsic(intr) => OpKind::Intrinsic(*intr),
+            OpType::Native(_, name) => OpKind::Native(name),
+            OpType::If(_) => OpKind::If,
+            OpType::While(_) => OpKind::While,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) enum OpType {
     PushInt(u64),
     Intrinsic(Intrinsic),
+    Native(NativeId, String),
     If(If),
     While(While),
 }
 
-#[derive(Debug)]
-pub(crate) enum Intrinsic {
+/// Kind of an operation, without the blocks nested within it
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum OpKind<'a> {
+    PushInt(u64),
+    Intrinsic(Intrinsic),
+    /// A word registered with [`Natives`](crate::Natives)
+    Native(&'a str),
+    If,
+    While,
+    /// Pops the condition guarding the block after the marker, as executed by
+    /// a [`Vm`](crate::Vm)
+    Branch(Marker),
+    /// Continues elsewhere in the enclosing block, as executed by a
+    /// [`Vm`](crate::Vm)
+    Jump,
+}
+
+impl OpKind<'_> {
+    /// Whether the operation contains blocks of other operations
+    pub fn is_block(&self) -> bool {
+        matches!(self, Self::If | Self::While)
+    }
+}
+
+impl fmt::Display for OpKind<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PushInt(val) => write!(f, "{}", val),
+            Self::Intrinsic(intr) => write!(f, "{}", intr),
+            Self::Native(name) => write!(f, "{}", name),
+            Self::If => write!(f, "{}", Marker::If),
+            Self::While => write!(f, "{}", Marker::While),
+            Self::Branch(marker) => write!(f, "{}", marker),
+            Self::Jump => write!(f, "jump"),
+        }
+    }
+}
+
+/// An operation about to be executed, as seen by an
+/// [`Observer`](crate::Observer)
+#[derive(Clone, Copy, Debug)]
+pub struct OpView<'a> {
+    pub(crate) id: OpId,
+    pub(crate) kind: OpKind<'a>,
+    pub(crate) loc: &'a FileLocation,
+    pub(crate) depth: usize,
+}
+
+impl<'a> OpView<'a> {
+    /// Identifies the operation, stable for as long as the program is. The
+    /// branches and jumps of a [`Vm`](crate::Vm) share the id of their `if`
+    /// or `while`.
+    pub fn id(&self) -> OpId {
+        self.id
+    }
+
+    pub fn kind(&self) -> OpKind<'a> {
+        self.kind
+    }
+
+    pub fn location(&self) -> &'a FileLocation {
+        self.loc
+    }
+
+    /// Number of blocks enclosing the operation, `0` at the top level
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// Number of values an operation takes from the stack, and how many it
+/// leaves in their place
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Arity {
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+impl Arity {
+    pub const fn new(inputs: usize, outputs: usize) -> Self {
+        Self { inputs, outputs }
+    }
+}
+
+/// Built-in words of the language
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Intrinsic {
     Dup,
     Swap,
     Drop,
@@ -45,9 +153,25 @@ pub(crate) enum Intrinsic {
     Subtract,
     Multiply,
     DivMod,
+    /// Stops the program, with the popped value as its exit code
+    Exit,
 }
 
 impl Intrinsic {
+    pub const ALL: [Self; 11] = [
+        Self::Dup,
+        Self::Swap,
+        Self::Drop,
+        Self::Print,
+        Self::Over,
+        Self::Rot,
+        Self::Plus,
+        Self::Subtract,
+        Self::Multiply,
+        Self::DivMod,
+        Self::Exit,
+    ];
+
     const DUP_TEXT: &'static str = "dup";
     const SWAP_TEXT: &'static str = "swap";
     const DROP_TEXT: &'static str = "drop";
@@ -58,8 +182,9 @@ impl Intrinsic {
     const SUBTRACT_TEXT: &'static str = "-";
     const MULTIPLY_TEXT: &'static str = "*";
     const DIV_MOD_TEXT: &'static str = "divmod";
+    const EXIT_TEXT: &'static str = "exit";
 
-    pub(crate) fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             Self::Dup => Self::DUP_TEXT,
             Self::Swap => Self::SWAP_TEXT,
@@ -71,11 +196,29 @@ impl Intrinsic {
             Self::Subtract => Self::SUBTRACT_TEXT,
             Self::Multiply => Self::MULTIPLY_TEXT,
             Self::DivMod => Self::DIV_MOD_TEXT,
+            Self::Exit => Self::EXIT_TEXT,
+        }
+    }
+
+    pub const fn arity(&self) -> Arity {
+        match self {
+            Self::Dup => Arity::new(1, 2),
+            Self::Swap => Arity::new(2, 2),
+            Self::Drop => Arity::new(1, 0),
+            Self::Print => Arity::new(1, 0),
+            Self::Over => Arity::new(2, 3),
+            Self::Rot => Arity::new(3, 3),
+            Self::Plus => Arity::new(2, 1),
+            Self::Subtract => Arity::new(2, 1),
+            Self::Multiply => Arity::new(2, 1),
+            Self::DivMod => Arity::new(2, 2),
+            Self::Exit => Arity::new(1, 0),
         }
     }
 }
 
-pub(crate) struct InvalidIntrinsicError;
+#[derive(Debug)]
+pub struct InvalidIntrinsicError;
 
 impl TryFrom<&str> for Intrinsic {
     type Error = InvalidIntrinsicError;
@@ -92,6 +235,7 @@ impl TryFrom<&str> for Intrinsic {
             Self::SUBTRACT_TEXT => Self::Subtract,
             Self::MULTIPLY_TEXT => Self::Multiply,
             Self::DIV_MOD_TEXT => Self::DivMod,
+            Self::EXIT_TEXT => Self::Exit,
             _ => return Err(InvalidIntrinsicError),
         })
     }
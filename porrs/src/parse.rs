@@ -1,14 +1,17 @@
-use std::fmt;
+use std::{error, fmt};
 
 use crate::error::InfoKind;
 use crate::lex::Lexer;
-use crate::op::{If, Intrinsic, Op, OpBlock, OpType, While};
+use crate::native::Natives;
+use crate::op::{If, Intrinsic, Op, OpBlock, OpId, OpType, While};
 use crate::program::FileLocation;
+use crate::suggest;
 use crate::token::{Marker, Token, TokenType};
 use crate::{Error, Result};
 
 #[derive(Debug)]
-pub(crate) enum ParsingError {
+#[non_exhaustive]
+pub enum ParsingError {
     UnexpectedMarker(Marker, UnexpectedMarker),
     MissingMarker(Marker, MissingMarker),
     UnknownWord(String),
@@ -36,8 +39,11 @@ impl fmt::Display for ParsingError {
     }
 }
 
+impl error::Error for ParsingError {}
+
 #[derive(Debug)]
-pub(crate) enum MissingMarker {
+#[non_exhaustive]
+pub enum MissingMarker {
     BlockNotClosed,
     RequiredByBlock(Marker, Marker),
 }
@@ -67,7 +73,8 @@ impl fmt::Display for MissingMarker {
 }
 
 #[derive(Debug)]
-pub(crate) enum UnexpectedMarker {
+#[non_exhaustive]
+pub enum UnexpectedMarker {
     General(String),
     FreeFloating(Marker),
     Repeated(Marker, Marker),
@@ -108,13 +115,27 @@ enum Parsed {
     Marker { marker: Marker, loc: FileLocation },
 }
 
-pub(crate) struct Parser {
+pub(crate) struct Parser<'n> {
     lexer: Lexer,
+    natives: &'n Natives,
+    /// Id of the next operation parsed
+    next_id: u32,
 }
 
-impl Parser {
-    pub(crate) fn from_lexer(lexer: Lexer) -> Self {
-        Self { lexer }
+impl<'n> Parser<'n> {
+    pub(crate) fn from_lexer(lexer: Lexer, natives: &'n Natives) -> Self {
+        Self {
+            lexer,
+            natives,
+            next_id: 0,
+        }
+    }
+
+    /// Numbers operations as they start, so blocks come before their contents
+    fn next_id(&mut self) -> OpId {
+        let id = OpId(self.next_id);
+        self.next_id += 1;
+        id
     }
 
     pub(crate) fn into_root_block(mut self) -> Result<OpBlock> {
@@ -150,6 +171,7 @@ impl Parser {
             TokenType::Word(word) => Parsed::Op(self.parse_word(word.as_str(), token.loc)?),
 
             TokenType::Int(val) => Parsed::Op(Op {
+                id: self.next_id(),
                 typ: OpType::PushInt(val),
                 loc: token.loc,
             }),
@@ -163,21 +185,57 @@ impl Parser {
     }
 
     fn parse_word(&mut self, text: &str, loc: FileLocation) -> Result<Op> {
-        match Intrinsic::try_from(text) {
-            Ok(intr) => Ok(Op {
+        let id = self.next_id();
+
+        if let Ok(intr) = Intrinsic::try_from(text) {
+            return Ok(Op {
+                id,
                 typ: OpType::Intrinsic(intr),
                 loc,
+            });
+        }
+
+        match self.natives.find(text) {
+            Some(native) => Ok(Op {
+                id,
+                typ: OpType::Native(native, text.to_owned()),
+                loc,
             }),
-            Err(_) => Err(ParsingError::UnknownWord(text.to_owned())
-                .into_error()
-                .add_loc(loc)),
+            None => Err(self.unknown_word_error(text, loc)),
+        }
+    }
+
+    fn known_words(&self) -> impl Iterator<Item = &str> {
+        let builtins = Intrinsic::ALL
+            .iter()
+            .map(|intr| intr.as_str())
+            .chain(Marker::ALL.iter().map(|marker| marker.as_str()));
+
+        builtins.chain(self.natives.names())
+    }
+
+    fn unknown_word_error(&self, text: &str, loc: FileLocation) -> Error {
+        let mut err = ParsingError::UnknownWord(text.to_owned())
+            .into_error()
+            .add_loc(loc.clone());
+
+        if let Some(hint) = suggest::forth_hint(text) {
+            return err.push_info(InfoKind::Hint(hint), loc);
+        }
+
+        let similar = suggest::similar_words(text, self.known_words());
+        if !similar.is_empty() {
+            let similar = similar.into_iter().map(str::to_owned).collect();
+            err = err.push_info(InfoKind::Suggestion(similar), loc);
         }
+
+        err
     }
 
     fn parse_marker(&mut self, marker: Marker, loc: FileLocation) -> Result<Parsed> {
         match marker {
-            Marker::If => self.parse_if(loc).map(|op| Parsed::Op(op)),
-            Marker::While => self.parse_while(loc).map(|op| Parsed::Op(op)),
+            Marker::If => self.parse_if(loc).map(Parsed::Op),
+            Marker::While => self.parse_while(loc).map(Parsed::Op),
             _ => Ok(Parsed::Marker { marker, loc }),
         }
     }
@@ -189,6 +247,7 @@ impl Parser {
             Else,
         }
 
+        let id = self.next_id();
         let mut if_op = If::new();
         let mut parse_state = ParseState::If;
 
@@ -241,6 +300,7 @@ impl Parser {
 
                     Marker::End => {
                         return Ok(Op {
+                            id,
                             typ: OpType::If(if_op),
                             loc: if_loc,
                         })
@@ -268,6 +328,7 @@ impl Parser {
             Do,
         }
 
+        let id = self.next_id();
         let mut while_op = While::new();
         let mut parse_state = ParseState::Cond;
 
@@ -302,6 +363,7 @@ impl Parser {
                         }
                         ParseState::Do => {
                             return Ok(Op {
+                                id,
                                 typ: OpType::While(while_op),
                                 loc: while_loc,
                             })
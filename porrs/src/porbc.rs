@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{error, fmt, io};
+
+use crate::bytecode::{Block, Bytecode, Instr};
+use crate::native::{NativeId, Natives};
+use crate::op::{Arity, Intrinsic, OpId};
+use crate::program::{FileLocation, FilePosition};
+use crate::token::Marker;
+use crate::{Error, Result};
+
+/// Version of the `.porbc` format, bumped on any incompatible change
+pub const PORBC_VERSION: u16 = 2;
+
+/// Bytes every `.porbc` file starts with
+pub const PORBC_MAGIC: [u8; 6] = *b"PORBC\0";
+
+/// Marks the absence of a block in the block fields
+const NO_BLOCK: u32 = u32::MAX;
+
+/// Markers which open the blocks instructions are nested in
+const BLOCK_MARKERS: [Marker; 2] = [Marker::If, Marker::While];
+
+/// Markers which branch instructions pop the condition of
+const BRANCH_MARKERS: [Marker; 3] = [Marker::If, Marker::IfStar, Marker::Do];
+
+const OP_PUSH_INT: u8 = 0;
+const OP_INTRINSIC: u8 = 1;
+const OP_NATIVE: u8 = 2;
+const OP_BRANCH: u8 = 3;
+const OP_JUMP: u8 = 4;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LoadingError {
+    FileIo(PathBuf, io::Error),
+    NotBytecode,
+    NotSnapshot,
+    NotRecording,
+    UnsupportedVersion {
+        found: u16,
+        expected: u16,
+    },
+    /// The file ended in the middle of the named section
+    Truncated(&'static str),
+    TrailingBytes(usize),
+    InvalidString(u32),
+    InvalidValue {
+        what: &'static str,
+        value: u64,
+    },
+    OutOfRange {
+        what: &'static str,
+        index: u32,
+        len: usize,
+    },
+    UnknownNative(String),
+    NativeArityMismatch(String),
+    /// A branch on this marker is not directly within a block it can belong
+    /// to
+    MisplacedBranch(Marker),
+    /// The snapshot was taken while running a different program
+    SnapshotMismatch,
+}
+
+impl fmt::Display for LoadingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use LoadingError::*;
+        match self {
+            FileIo(path, err) => write!(f, "Failed to read the file {:?}: {}", path, err),
+            NotBytecode => write!(f, "Not a porrs bytecode file"),
+            NotSnapshot => write!(f, "Not a porrs snapshot file"),
+            NotRecording => write!(f, "Not a porrs input recording"),
+            UnsupportedVersion { found, expected } => write!(
+                f,
+                "Format version {} is not supported, expected version {}",
+                found, expected
+            ),
+            Truncated(section) => write!(f, "File is truncated within the {} section", section),
+            TrailingBytes(count) => write!(f, "{} unexpected bytes after the last section", count),
+            InvalidString(index) => write!(f, "String {} is not valid UTF-8", index),
+            InvalidValue { what, value } => write!(f, "Invalid {} `{}`", what, value),
+            OutOfRange { what, index, len } => write!(
+                f,
+                "{} index {} is out of range, there are only {}",
+                what, index, len
+            ),
+            UnknownNative(name) => write!(f, "Native word `{}` is not registered", name),
+            NativeArityMismatch(name) => write!(
+                f,
+                "Native word `{}` is registered with a different arity than it was compiled with",
+                name
+            ),
+            MisplacedBranch(marker) => write!(
+                f,
+                "`{}` branch is not directly within a matching block",
+                marker
+            ),
+            SnapshotMismatch => write!(f, "Snapshot was taken of a different program"),
+        }
+    }
+}
+
+impl error::Error for LoadingError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::FileIo(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Builds the tables referenced by instructions while encoding
+#[derive(Default)]
+struct Encoder<'a> {
+    buf: Vec<u8>,
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    natives: Vec<NativeId>,
+    native_ids: HashMap<NativeId, u32>,
+    locs: Vec<&'a FileLocation>,
+    loc_ids: HashMap<&'a FileLocation, u32>,
+}
+
+impl<'a> Encoder<'a> {
+    fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    fn u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    fn u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    fn count(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn string(&mut self, string: String) -> u32 {
+        let strings = &mut self.strings;
+        *self.string_ids.entry(string.clone()).or_insert_with(|| {
+            strings.push(string);
+            strings.len() as u32 - 1
+        })
+    }
+
+    fn native(&mut self, id: NativeId) -> u32 {
+        let natives = &mut self.natives;
+        *self.native_ids.entry(id).or_insert_with(|| {
+            natives.push(id);
+            natives.len() as u32 - 1
+        })
+    }
+
+    fn loc(&mut self, loc: &'a FileLocation) -> u32 {
+        let locs = &mut self.locs;
+        *self.loc_ids.entry(loc).or_insert_with(|| {
+            locs.push(loc);
+            locs.len() as u32 - 1
+        })
+    }
+}
+
+/// Reads the sections of a `.porbc` file, or any other file laid out the same
+/// way, in order
+pub(crate) struct Decoder<'b> {
+    pub(crate) bytes: &'b [u8],
+    pub(crate) section: &'static str,
+}
+
+impl Decoder<'_> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&[u8]> {
+        if self.bytes.len() < len {
+            return Err(Error::from(LoadingError::Truncated(self.section)));
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        self.u32().map(|val| val as usize)
+    }
+
+    /// Reads an index into a table of `len` entries
+    fn index(&mut self, what: &'static str, len: usize) -> Result<usize> {
+        let index = self.u32()?;
+
+        if (index as usize) < len {
+            Ok(index as usize)
+        } else {
+            Err(Error::from(LoadingError::OutOfRange { what, index, len }))
+        }
+    }
+
+    /// Reads an index into a table of `len` entries, or [`NO_BLOCK`]
+    fn optional_index(&mut self, what: &'static str, len: usize) -> Result<Option<usize>> {
+        let index = self.u32()?;
+
+        if index == NO_BLOCK {
+            Ok(None)
+        } else if (index as usize) < len {
+            Ok(Some(index as usize))
+        } else {
+            Err(Error::from(LoadingError::OutOfRange { what, index, len }))
+        }
+    }
+
+    /// Reads a marker, which has to be one of `allowed`
+    fn marker(&mut self, what: &'static str, allowed: &[Marker]) -> Result<Marker> {
+        let value = self.u8()?;
+        Marker::ALL
+            .get(value as usize)
+            .copied()
+            .filter(|marker| allowed.contains(marker))
+            .ok_or_else(|| invalid_value(what, value.into()))
+    }
+}
+
+fn invalid_value(what: &'static str, value: u64) -> Error {
+    Error::from(LoadingError::InvalidValue { what, value })
+}
+
+impl Bytecode {
+    /// Encodes the bytecode in the `.porbc` format, with all integers in
+    /// little-endian byte order:
+    ///
+    /// ```text
+    /// magic         "PORBC\0"
+    /// version       u16, PORBC_VERSION
+    /// strings       u32 count, then per string:
+    ///                 u32 byte length, UTF-8 bytes
+    /// natives       u32 count, then per native word:
+    ///                 u32 name string, u32 inputs, u32 outputs
+    /// locations     u32 count, then per location:
+    ///                 u32 path string, u8 has position,
+    ///                 then if it is 1: u32 line, u32 column, u32 length
+    /// blocks        u32 count, then per `if` or `while` block:
+    ///                 u8 marker, u32 location, u32 parent block
+    /// instructions  u32 count, then per instruction:
+    ///                 u8 opcode, operands, u32 location, u32 block,
+    ///                 u32 operation
+    /// ```
+    ///
+    /// Tables refer to each other by their index within them. Blocks which
+    /// are not nested in any other block, and instructions outside of any
+    /// block, have the block `0xffffffff`. A block's parent always comes
+    /// before it. Markers are numbered in the order `if`, `if*`, `else`,
+    /// `while`, `do`, `end`. Blocks are either `if` or `while`, and branches
+    /// on `if` and `if*` belong directly to an `if` block, those on `do` to a
+    /// `while` block. Operations are the [`OpId`]s of the operations the
+    /// instructions were compiled from.
+    ///
+    /// | Opcode | Instruction | Operands                                     |
+    /// |--------|-------------|----------------------------------------------|
+    /// | 0      | push        | u64 value                                    |
+    /// | 1      | intrinsic   | u8 intrinsic, numbered as [`Intrinsic::ALL`] |
+    /// | 2      | native      | u32 native                                   |
+    /// | 3      | branch      | u8 marker, u32 target if false               |
+    /// | 4      | jump        | u32 target                                   |
+    ///
+    /// Targets are instruction indices, where the instruction count means the
+    /// end of the program.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut enc = Encoder::default();
+
+        // Instructions are encoded first to collect the tables they refer to
+        enc.count(self.instrs.len());
+        for (pc, instr) in self.instrs.iter().enumerate() {
+            match instr {
+                Instr::PushInt(val) => {
+                    enc.u8(OP_PUSH_INT);
+                    enc.u64(*val);
+                }
+                Instr::Intrinsic(intr) => {
+                    enc.u8(OP_INTRINSIC);
+                    enc.u8(Intrinsic::ALL.iter().position(|i| i == intr).unwrap() as u8);
+                }
+                Instr::Native(id) => {
+                    let native = enc.native(*id);
+                    enc.u8(OP_NATIVE);
+                    enc.u32(native);
+                }
+                Instr::Branch { marker, target } => {
+                    enc.u8(OP_BRANCH);
+                    enc.u8(Marker::ALL.iter().position(|m| m == marker).unwrap() as u8);
+                    enc.count(*target);
+                }
+                Instr::Jump(target) => {
+                    enc.u8(OP_JUMP);
+                    enc.count(*target);
+                }
+            }
+
+            let loc = enc.loc(&self.locs[pc]);
+            enc.u32(loc);
+            enc.u32(self.parents[pc].map_or(NO_BLOCK, |block| block as u32));
+            enc.u32(self.ids[pc].0);
+        }
+        let instrs = std::mem::take(&mut enc.buf);
+
+        let mut blocks = Vec::new();
+        for block in &self.blocks {
+            let loc = enc.loc(&block.loc);
+            blocks.push((block.marker, loc, block.parent));
+        }
+
+        let mut locs = Vec::new();
+        for loc in enc.locs.clone() {
+            let path = enc.string(loc.path().to_string_lossy().into_owned());
+            locs.push((path, loc.position().cloned()));
+        }
+
+        let mut natives = Vec::new();
+        for id in enc.natives.clone() {
+            let native = self.natives.get(id);
+            let name = enc.string(native.name.clone());
+            natives.push((name, native.arity));
+        }
+
+        enc.buf.extend_from_slice(&PORBC_MAGIC);
+        enc.buf.extend_from_slice(&PORBC_VERSION.to_le_bytes());
+
+        enc.count(enc.strings.len());
+        for string in std::mem::take(&mut enc.strings) {
+            enc.count(string.len());
+            enc.buf.extend_from_slice(string.as_bytes());
+        }
+
+        enc.count(natives.len());
+        for (name, arity) in natives {
+            enc.u32(name);
+            enc.count(arity.inputs);
+            enc.count(arity.outputs);
+        }
+
+        enc.count(locs.len());
+        for (path, pos) in locs {
+            enc.u32(path);
+            match pos {
+                Some(pos) => {
+                    enc.u8(1);
+                    enc.count(pos.line);
+                    enc.count(pos.col);
+                    enc.count(pos.len);
+                }
+                None => enc.u8(0),
+            }
+        }
+
+        enc.count(blocks.len());
+        for (marker, loc, parent) in blocks {
+            enc.u8(Marker::ALL.iter().position(|m| *m == marker).unwrap() as u8);
+            enc.u32(loc);
+            enc.u32(parent.map_or(NO_BLOCK, |block| block as u32));
+        }
+
+        enc.buf.extend_from_slice(&instrs);
+        enc.buf
+    }
+
+    /// Decodes bytecode in the `.porbc` format described in
+    /// [`to_bytes`](Bytecode::to_bytes), resolving its native words in
+    /// `natives`
+    pub fn from_bytes(bytes: &[u8], natives: Arc<Natives>) -> Result<Self> {
+        let mut dec = Decoder {
+            bytes,
+            section: "header",
+        };
+
+        if dec.take(PORBC_MAGIC.len()).ok() != Some(&PORBC_MAGIC[..]) {
+            return Err(Error::from(LoadingError::NotBytecode));
+        }
+
+        let version = dec.u16()?;
+        if version != PORBC_VERSION {
+            return Err(Error::from(LoadingError::UnsupportedVersion {
+                found: version,
+                expected: PORBC_VERSION,
+            }));
+        }
+
+        dec.section = "strings";
+        let mut strings = Vec::new();
+        for index in 0..dec.u32()? {
+            let len = dec.usize()?;
+            let string = std::str::from_utf8(dec.take(len)?)
+                .map_err(|_| Error::from(LoadingError::InvalidString(index)))?;
+            strings.push(string.to_owned());
+        }
+
+        dec.section = "natives";
+        let mut native_ids = Vec::new();
+        for _ in 0..dec.u32()? {
+            let name = &strings[dec.index("string", strings.len())?];
+            let arity = Arity::new(dec.usize()?, dec.usize()?);
+
+            let id = natives
+                .find(name)
+                .ok_or_else(|| Error::from(LoadingError::UnknownNative(name.clone())))?;
+            if natives.get(id).arity != arity {
+                return Err(Error::from(LoadingError::NativeArityMismatch(name.clone())));
+            }
+
+            native_ids.push(id);
+        }
+
+        dec.section = "locations";
+        let mut locs = Vec::new();
+        for _ in 0..dec.u32()? {
+            let path = PathBuf::from(&strings[dec.index("string", strings.len())?]);
+            let pos = match dec.u8()? {
+                0 => None,
+                1 => Some(FilePosition {
+                    line: dec.usize()?,
+                    col: dec.usize()?,
+                    len: dec.usize()?,
+                }),
+                value => return Err(invalid_value("position flag", value.into())),
+            };
+
+            locs.push(FileLocation { path, pos });
+        }
+
+        dec.section = "blocks";
+        let mut blocks = Vec::new();
+        for _ in 0..dec.u32()? {
+            let marker = dec.marker("block marker", &BLOCK_MARKERS)?;
+            let loc = locs[dec.index("location", locs.len())?].clone();
+            let parent = dec.optional_index("block", blocks.len())?;
+
+            blocks.push(Block {
+                marker,
+                loc,
+                parent,
+            });
+        }
+
+        dec.section = "instructions";
+        let count = dec.usize()?;
+        let mut bytecode = Self {
+            instrs: Vec::new(),
+            locs: Vec::new(),
+            ids: Vec::new(),
+            parents: Vec::new(),
+            blocks,
+            natives,
+        };
+
+        for _ in 0..count {
+            let instr = match dec.u8()? {
+                OP_PUSH_INT => Instr::PushInt(dec.u64()?),
+                OP_INTRINSIC => {
+                    let value = dec.u8()?;
+                    let intr = Intrinsic::ALL
+                        .get(value as usize)
+                        .ok_or_else(|| invalid_value("intrinsic", value.into()))?;
+                    Instr::Intrinsic(*intr)
+                }
+                OP_NATIVE => Instr::Native(native_ids[dec.index("native", native_ids.len())?]),
+                OP_BRANCH => Instr::Branch {
+                    marker: dec.marker("branch marker", &BRANCH_MARKERS)?,
+                    target: dec.index("target", count + 1)?,
+                },
+                OP_JUMP => Instr::Jump(dec.index("target", count + 1)?),
+                opcode => return Err(invalid_value("opcode", opcode.into())),
+            };
+
+            let loc = locs[dec.index("location", locs.len())?].clone();
+            let parent = dec.optional_index("block", bytecode.blocks.len())?;
+            let id = OpId(dec.u32()?);
+
+            // Branches are only ever emitted within the block they guard
+            if let Instr::Branch { marker, .. } = instr {
+                let block = match marker {
+                    Marker::Do => Marker::While,
+                    _ => Marker::If,
+                };
+                if parent.map(|id| bytecode.blocks[id].marker) != Some(block) {
+                    return Err(Error::from(LoadingError::MisplacedBranch(marker)));
+                }
+            }
+
+            bytecode.instrs.push(instr);
+            bytecode.locs.push(loc);
+            bytecode.ids.push(id);
+            bytecode.parents.push(parent);
+        }
+
+        if !dec.bytes.is_empty() {
+            return Err(Error::from(LoadingError::TrailingBytes(dec.bytes.len())));
+        }
+
+        Ok(bytecode)
+    }
+
+    /// Loads the `.porbc` file at `path`, resolving its native words in
+    /// `natives`
+    pub fn from_path(path: impl AsRef<Path>, natives: Arc<Natives>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let bytes = fs::read(path)
+            .map_err(|err| Error::from(LoadingError::FileIo(path.to_path_buf(), err)))?;
+
+        let bytecode = Self::from_bytes(&bytes, natives)
+            .map_err(|err| err.add_loc(FileLocation::from_path(path)))?;
+
+        log::info!("Loaded bytecode from file: {}", path.display());
+
+        Ok(bytecode)
+    }
+}
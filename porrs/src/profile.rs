@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::op::{OpId, OpKind, OpView};
+use crate::program::FileLocation;
+use crate::simulate::Observer;
+use crate::token::Marker;
+use crate::Result;
+
+/// Execution count and host time spent on something being profiled
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProfileEntry {
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl ProfileEntry {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    /// `if` or `while`, for operations containing blocks
+    block: Option<Marker>,
+    start: Instant,
+    child_time: Duration,
+}
+
+/// An [`Observer`] measuring how often each operation runs and how much host
+/// time it takes.
+///
+/// Time spent in an operation is attributed to its source line, excluding
+/// the time of any operations nested within it. `if` and `while` blocks are
+/// additionally profiled as a whole, including their nested operations.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    frames: Vec<Frame>,
+    /// Operation of each frame, outermost first
+    stack: Vec<OpId>,
+    /// Location of each operation, only looked up when writing the profile so
+    /// that the profiler itself adds as little time as possible to each
+    /// operation
+    locs: HashMap<OpId, FileLocation>,
+    ops: HashMap<OpId, ProfileEntry>,
+    blocks: HashMap<OpId, (Marker, ProfileEntry)>,
+    /// Self time by stack of operations, innermost last
+    folded: HashMap<Vec<OpId>, Duration>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Profile of each source line, hottest first
+    pub fn lines(&self) -> Vec<(&Path, usize, ProfileEntry)> {
+        let mut lines = HashMap::<_, ProfileEntry>::new();
+        for (key, entry) in &self.ops {
+            let loc = &self.locs[key];
+            let line = lines
+                .entry((loc.path(), loc.line().unwrap_or(0)))
+                .or_default();
+            line.count += entry.count;
+            line.time += entry.time;
+        }
+
+        let mut lines = lines
+            .into_iter()
+            .map(|((path, line), entry)| (path, line, entry))
+            .collect::<Vec<_>>();
+
+        lines.sort_by(|a, b| b.2.time.cmp(&a.2.time).then((a.0, a.1).cmp(&(b.0, b.1))));
+        lines
+    }
+
+    /// Profile of each `if` and `while` block, hottest first
+    pub fn blocks(&self) -> Vec<(&FileLocation, &str, ProfileEntry)> {
+        let mut blocks = self
+            .blocks
+            .iter()
+            .map(|(key, (marker, entry))| (&self.locs[key], marker.as_str(), *entry))
+            .collect::<Vec<_>>();
+
+        blocks.sort_by(|a, b| {
+            b.2.time
+                .cmp(&a.2.time)
+                .then_with(|| a.0.to_string().cmp(&b.0.to_string()))
+        });
+        blocks
+    }
+
+    /// Writes tables of the `top` hottest lines and blocks
+    pub fn write_report(&self, w: &mut impl Write, top: usize) -> io::Result<()> {
+        let total = self
+            .ops
+            .values()
+            .map(|entry| entry.time)
+            .sum::<Duration>()
+            .max(Duration::from_nanos(1));
+
+        writeln!(w, "Hot lines:")?;
+        writeln!(w, "{:>12} {:>12} {:>7}  LOCATION", "COUNT", "SELF", "%")?;
+        for (path, line, entry) in self.lines().into_iter().take(top) {
+            writeln!(
+                w,
+                "{:>12} {:>12} {:>6.2}%  {}:{}",
+                entry.count,
+                format!("{:.3?}", entry.time),
+                entry.time.as_secs_f64() / total.as_secs_f64() * 100.0,
+                path.display(),
+                line
+            )?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "Hot blocks:")?;
+        writeln!(w, "{:>12} {:>12} {:>7}  BLOCK", "COUNT", "TOTAL", "%")?;
+        for (loc, name, entry) in self.blocks().into_iter().take(top) {
+            writeln!(
+                w,
+                "{:>12} {:>12} {:>6.2}%  `{}` at {}",
+                entry.count,
+                format!("{:.3?}", entry.time),
+                entry.time.as_secs_f64() / total.as_secs_f64() * 100.0,
+                name,
+                loc
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the profile as folded stacks, one `frame;frame;line nanoseconds`
+    /// entry per line, as consumed by `flamegraph.pl` and `inferno`
+    pub fn write_folded(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut folded = HashMap::<String, Duration>::new();
+        for (keys, time) in &self.folded {
+            let mut stack = String::new();
+            for (i, key) in keys.iter().enumerate() {
+                let loc = &self.locs[key];
+                if i > 0 {
+                    stack.push(';');
+                }
+
+                // Blocks are frames of their own, other operations are
+                // merged by line
+                match self.blocks.get(key) {
+                    Some((marker, _)) => {
+                        let _ = write!(stack, "{}@{}", marker, loc);
+                    }
+                    None => {
+                        let _ = write!(
+                            stack,
+                            "{}:{}",
+                            loc.path().display(),
+                            loc.line().unwrap_or(0)
+                        );
+                    }
+                }
+            }
+
+            *folded.entry(stack).or_default() += *time;
+        }
+
+        let mut folded = folded.into_iter().collect::<Vec<_>>();
+        folded.sort();
+
+        for (stack, time) in folded {
+            writeln!(w, "{} {}", stack, time.as_nanos())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Observer for Profiler {
+    fn before_op(&mut self, op: &OpView<'_>, _stack: &[u64]) -> Result<()> {
+        let block = match op.kind() {
+            OpKind::If => Some(Marker::If),
+            OpKind::While => Some(Marker::While),
+            _ => None,
+        };
+
+        self.stack.push(op.id());
+        self.frames.push(Frame {
+            block,
+            start: Instant::now(),
+            child_time: Duration::ZERO,
+        });
+
+        Ok(())
+    }
+
+    fn after_op(&mut self, op: &OpView<'_>, _stack: &[u64]) -> Result<()> {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        let time = frame.start.elapsed();
+        let self_time = time.saturating_sub(frame.child_time);
+
+        if let Some(parent) = self.frames.last_mut() {
+            parent.child_time += time;
+        }
+
+        let key = op.id();
+        self.locs
+            .entry(key)
+            .or_insert_with(|| op.location().clone());
+        self.ops.entry(key).or_default().add(self_time);
+
+        if let Some(marker) = frame.block {
+            self.blocks
+                .entry(key)
+                .or_insert((marker, ProfileEntry::default()))
+                .1
+                .add(time);
+        }
+
+        match self.folded.get_mut(self.stack.as_slice()) {
+            Some(folded) => *folded += self_time,
+            None => {
+                self.folded.insert(self.stack.clone(), self_time);
+            }
+        }
+        self.stack.pop();
+
+        Ok(())
+    }
+}
@@ -1,19 +1,40 @@
 use std::fmt;
+use std::io::Cursor;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::lex::Lexer;
+use crate::native::Natives;
 use crate::op::OpBlock;
 use crate::parse::Parser;
 use crate::Result;
 
-#[derive(Clone, Debug)]
-pub(crate) struct FilePosition {
+/// Position of a span of text within a file, with lines and columns starting
+/// at 1
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FilePosition {
     pub(crate) line: usize,
     pub(crate) col: usize,
+    pub(crate) len: usize,
 }
 
-#[derive(Clone, Debug)]
-pub(crate) struct FileLocation {
+impl FilePosition {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.col
+    }
+
+    /// Length of the span in bytes, `0` if it does not cover any text
+    pub fn span_len(&self) -> usize {
+        self.len
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FileLocation {
     pub(crate) path: PathBuf,
     pub(crate) pos: Option<FilePosition>,
 }
@@ -25,6 +46,28 @@ impl FileLocation {
             pos: None,
         }
     }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Position within the file, if the location refers to more than the file
+    /// as a whole
+    pub fn position(&self) -> Option<&FilePosition> {
+        self.pos.as_ref()
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.pos.as_ref().map(FilePosition::line)
+    }
+
+    pub fn column(&self) -> Option<usize> {
+        self.pos.as_ref().map(FilePosition::column)
+    }
+
+    pub fn span_len(&self) -> Option<usize> {
+        self.pos.as_ref().map(FilePosition::span_len)
+    }
 }
 
 impl fmt::Display for FileLocation {
@@ -41,17 +84,50 @@ impl fmt::Display for FileLocation {
 
 pub struct Program {
     pub(crate) root_block: OpBlock,
+    pub(crate) natives: Arc<Natives>,
 }
 
 impl Program {
     pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_path_with(path, Arc::default())
+    }
+
+    /// Parses the program at `path`, which may use any word in `natives`
+    pub fn from_path_with(path: impl AsRef<Path>, natives: Arc<Natives>) -> Result<Self> {
         let lexer = Lexer::from_path(&path)?;
-        let parser = Parser::from_lexer(lexer);
-        let root_block = parser.into_root_block()?;
+        let program = Self::from_lexer(lexer, natives)?;
 
         log::info!("Parsed program at file: {}", path.as_ref().display());
+
+        Ok(program)
+    }
+
+    /// Parses a program held in memory, with `name` standing in for the file
+    /// path in locations
+    pub fn from_source(name: impl AsRef<Path>, source: impl Into<String>) -> Result<Self> {
+        Self::from_source_with(name, source, Arc::default())
+    }
+
+    /// Parses a program held in memory, which may use any word in `natives`
+    pub fn from_source_with(
+        name: impl AsRef<Path>,
+        source: impl Into<String>,
+        natives: Arc<Natives>,
+    ) -> Result<Self> {
+        let lexer = Lexer::from_reader(name, Cursor::new(source.into()));
+
+        Self::from_lexer(lexer, natives)
+    }
+
+    fn from_lexer(lexer: Lexer, natives: Arc<Natives>) -> Result<Self> {
+        let parser = Parser::from_lexer(lexer, &natives);
+        let root_block = parser.into_root_block()?;
+
         log::trace!("Root Block: {:#?}", root_block);
 
-        Ok(Program { root_block })
+        Ok(Program {
+            root_block,
+            natives,
+        })
     }
 }
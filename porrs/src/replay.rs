@@ -0,0 +1,509 @@
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::{error, fmt};
+
+use crate::porbc::{Decoder, LoadingError};
+use crate::program::FileLocation;
+use crate::simulate::SimulationError;
+use crate::{Error, Result};
+
+/// Version of the recording format, bumped on any incompatible change
+pub const RECORDING_VERSION: u16 = 1;
+
+/// Bytes every recording file starts with
+pub const RECORDING_MAGIC: [u8; 8] = *b"PORREC\0\0";
+
+/// How a replayed program asked for different input than was recorded
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Divergence {
+    /// A native read input where a different one did in the recording
+    UnexpectedNative { expected: String, found: String },
+    /// A native read input after every recorded read was replayed
+    Exhausted(String),
+    /// A native read beyond the input it was shown in the recording
+    ReadPastRecording(String),
+    /// A native consumed a different number of bytes than in the recording
+    Consumed {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// The program finished before replaying every recorded read
+    Unused(usize),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Divergence::*;
+        match self {
+            UnexpectedNative { expected, found } => write!(
+                f,
+                "`{}` read input where `{}` did in the recording",
+                found, expected
+            ),
+            Exhausted(name) => write!(f, "`{}` read input but none is left in the recording", name),
+            ReadPastRecording(name) => write!(f, "`{}` read more input than was recorded", name),
+            Consumed {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` consumed {} bytes of input, but {} in the recording",
+                name, found, expected
+            ),
+            Unused(count) => write!(
+                f,
+                "Program finished with {} recorded read{} left",
+                count,
+                if *count == 1 { "" } else { "s" }
+            ),
+        }
+    }
+}
+
+impl error::Error for Divergence {}
+
+/// Input shown to a single call of a native
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct NativeRead {
+    native: String,
+    /// Every byte the native saw, including those it only peeked at
+    seen: Vec<u8>,
+    consumed: usize,
+    /// Whether the native reached the end of the input
+    eof: bool,
+}
+
+/// Input consumed by [`Natives`](crate::Natives) during a simulation, which
+/// can be fed back to reproduce the run exactly.
+///
+/// A [`Simulator`](crate::Simulator) or [`Vm`](crate::Vm) records one with
+/// `record_input` and replays it with `with_replay`. Only natives read input,
+/// so the recording holds what each of them read, in order.
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::io::BufRead;
+/// use std::sync::Arc;
+///
+/// use porrs::{Arity, Natives, Program, Simulator};
+///
+/// let mut natives = Natives::new();
+/// natives.register("read-line", Arity::new(0, 1), |ctx| {
+///     let mut line = String::new();
+///     ctx.input().read_line(&mut line)?;
+///     ctx.push(line.trim().parse()?);
+///     Ok(())
+/// })?;
+/// let program = Program::from_source_with("<example>", "read-line", Arc::new(natives))?;
+///
+/// let mut simulator = Simulator::new(&program)
+///     .with_input("42\n".as_bytes())
+///     .record_input();
+/// simulator.run()?;
+/// let recording = simulator.recording().unwrap().clone();
+///
+/// let outcome = Simulator::new(&program).with_replay(recording).run()?;
+/// assert_eq!(outcome.stack, [42]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recording {
+    reads: Vec<NativeRead>,
+}
+
+impl Recording {
+    /// Number of recorded native calls which read input
+    pub fn len(&self) -> usize {
+        self.reads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty()
+    }
+
+    /// Encodes the recording with all integers in little-endian byte order:
+    ///
+    /// ```text
+    /// magic      "PORREC\0\0"
+    /// version    u16, RECORDING_VERSION
+    /// reads      u64 count, then for each read:
+    ///   native   u64 length, then the UTF-8 name
+    ///   seen     u64 length, then the bytes shown to the native
+    ///   consumed u64, number of those bytes consumed
+    ///   eof      u8, 1 if the native reached the end of the input
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        buf.extend(RECORDING_MAGIC);
+        buf.extend(RECORDING_VERSION.to_le_bytes());
+        buf.extend((self.reads.len() as u64).to_le_bytes());
+        for read in &self.reads {
+            buf.extend((read.native.len() as u64).to_le_bytes());
+            buf.extend(read.native.as_bytes());
+            buf.extend((read.seen.len() as u64).to_le_bytes());
+            buf.extend(&read.seen);
+            buf.extend((read.consumed as u64).to_le_bytes());
+            buf.push(u8::from(read.eof));
+        }
+
+        buf
+    }
+
+    /// Decodes a recording in the format described in
+    /// [`to_bytes`](Recording::to_bytes)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut dec = Decoder {
+            bytes,
+            section: "header",
+        };
+
+        if dec.take(RECORDING_MAGIC.len()).ok() != Some(&RECORDING_MAGIC[..]) {
+            return Err(Error::from(LoadingError::NotRecording));
+        }
+
+        let version = dec.u16()?;
+        if version != RECORDING_VERSION {
+            return Err(Error::from(LoadingError::UnsupportedVersion {
+                found: version,
+                expected: RECORDING_VERSION,
+            }));
+        }
+
+        dec.section = "reads";
+        let count = dec.u64()?;
+        let mut reads = Vec::new();
+        for index in 0..count {
+            let len = dec.u64()?;
+            let native = dec.take(len.try_into().unwrap_or(usize::MAX))?;
+            let native = String::from_utf8(native.to_vec()).map_err(|_| {
+                Error::from(LoadingError::InvalidString(
+                    index.try_into().unwrap_or(u32::MAX),
+                ))
+            })?;
+
+            let len = dec.u64()?;
+            let seen = dec.take(len.try_into().unwrap_or(usize::MAX))?.to_vec();
+
+            let consumed = dec.u64()?;
+            if consumed > seen.len() as u64 {
+                return Err(Error::from(LoadingError::InvalidValue {
+                    what: "consumed byte count",
+                    value: consumed,
+                }));
+            }
+
+            let eof = match dec.u8()? {
+                0 => false,
+                1 => true,
+                value => {
+                    return Err(Error::from(LoadingError::InvalidValue {
+                        what: "end of input flag",
+                        value: value.into(),
+                    }))
+                }
+            };
+
+            reads.push(NativeRead {
+                native,
+                seen,
+                consumed: consumed as usize,
+                eof,
+            });
+        }
+
+        if !dec.bytes.is_empty() {
+            return Err(Error::from(LoadingError::TrailingBytes(dec.bytes.len())));
+        }
+
+        Ok(Self { reads })
+    }
+
+    /// Loads the recording file at `path`
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let bytes = fs::read(path)
+            .map_err(|err| Error::from(LoadingError::FileIo(path.to_path_buf(), err)))?;
+
+        Self::from_bytes(&bytes).map_err(|err| err.add_loc(FileLocation::from_path(path)))
+    }
+}
+
+#[derive(Debug)]
+enum Tape {
+    Live,
+    Record(Recording),
+    Replay { recording: Recording, next: usize },
+}
+
+/// Stdin, locked on the first read until dropped
+struct LazyStdin(Option<io::StdinLock<'static>>);
+
+impl LazyStdin {
+    fn lock(&mut self) -> &mut io::StdinLock<'static> {
+        self.0.get_or_insert_with(|| io::stdin().lock())
+    }
+}
+
+impl Read for LazyStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl BufRead for LazyStdin {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.lock().fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.lock().consume(amt)
+    }
+}
+
+/// Input of a simulation, which natives read through [`Input::call`]
+pub(crate) struct Input<'a> {
+    /// Where natives read from, stdin when `None`. Stdin is only locked while
+    /// a native runs, leaving it to the host the rest of the time.
+    source: Option<Box<dyn BufRead + 'a>>,
+    tape: Tape,
+}
+
+impl<'a> Input<'a> {
+    /// Reads from stdin
+    pub(crate) fn stdin() -> Self {
+        Self {
+            source: None,
+            tape: Tape::Live,
+        }
+    }
+
+    pub(crate) fn set_source(&mut self, source: impl BufRead + 'a) {
+        self.source = Some(Box::new(source));
+    }
+
+    pub(crate) fn record(&mut self) {
+        self.tape = Tape::Record(Recording::default());
+    }
+
+    pub(crate) fn replay(&mut self, recording: Recording) {
+        self.tape = Tape::Replay { recording, next: 0 };
+    }
+
+    pub(crate) fn recording(&self) -> Option<&Recording> {
+        match &self.tape {
+            Tape::Record(recording) => Some(recording),
+            _ => None,
+        }
+    }
+
+    /// Calls `func` with the input for the native `name`, recording what it
+    /// reads or replaying what it read before
+    pub(crate) fn call<R>(
+        &mut self,
+        name: &str,
+        func: impl FnOnce(&mut dyn BufRead) -> R,
+    ) -> Result<R> {
+        let mut stdin = LazyStdin(None);
+        let source: &mut dyn BufRead = match &mut self.source {
+            Some(source) => source,
+            None => &mut stdin,
+        };
+
+        match &mut self.tape {
+            Tape::Live => Ok(func(source)),
+
+            Tape::Record(recording) => {
+                let mut recorder = Recorder {
+                    inner: source,
+                    seen: Vec::new(),
+                    consumed: 0,
+                    eof: false,
+                    touched: false,
+                };
+                let result = func(&mut recorder);
+
+                if recorder.touched {
+                    recording.reads.push(NativeRead {
+                        native: name.to_owned(),
+                        seen: recorder.seen,
+                        consumed: recorder.consumed,
+                        eof: recorder.eof,
+                    });
+                }
+
+                Ok(result)
+            }
+
+            Tape::Replay { recording, next } => {
+                let mut player = Player {
+                    name,
+                    reads: &recording.reads,
+                    next,
+                    read: None,
+                    pos: 0,
+                    divergence: None,
+                };
+                let result = func(&mut player);
+
+                if let Some(divergence) = player.divergence {
+                    return Err(Error::from(SimulationError::ReplayDiverged(divergence)));
+                }
+                if let Some(read) = player.read {
+                    if player.pos != read.consumed {
+                        return Err(Error::from(SimulationError::ReplayDiverged(
+                            Divergence::Consumed {
+                                name: name.to_owned(),
+                                expected: read.consumed,
+                                found: player.pos,
+                            },
+                        )));
+                    }
+                }
+
+                Ok(result)
+            }
+        }
+    }
+
+    /// Checks that a replayed program read all of its recorded input
+    pub(crate) fn finish(&self) -> Result<()> {
+        match &self.tape {
+            Tape::Replay { recording, next } if *next < recording.reads.len() => Err(Error::from(
+                SimulationError::ReplayDiverged(Divergence::Unused(recording.reads.len() - next)),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Passes reads through to the live input, keeping every byte shown
+struct Recorder<'r> {
+    inner: &'r mut dyn BufRead,
+    seen: Vec<u8>,
+    consumed: usize,
+    eof: bool,
+    touched: bool,
+}
+
+impl Recorder<'_> {
+    /// Keeps the bytes in `buf`, which start at the first unconsumed byte
+    fn see(seen: &mut Vec<u8>, consumed: usize, buf: &[u8]) {
+        let known = seen.len() - consumed;
+        if buf.len() > known {
+            seen.extend(&buf[known..]);
+        }
+    }
+}
+
+impl Read for Recorder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.touched = true;
+
+        let len = self.inner.read(buf)?;
+        if len == 0 && !buf.is_empty() {
+            self.eof = true;
+        }
+
+        Self::see(&mut self.seen, self.consumed, &buf[..len]);
+        self.consumed += len;
+        Ok(len)
+    }
+}
+
+impl BufRead for Recorder<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.touched = true;
+
+        let buf = self.inner.fill_buf()?;
+        if buf.is_empty() {
+            self.eof = true;
+        }
+
+        Self::see(&mut self.seen, self.consumed, buf);
+        Ok(buf)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.consumed += amt;
+    }
+}
+
+/// Shows a native what it read in the recording, noting any divergence
+struct Player<'r> {
+    name: &'r str,
+    reads: &'r [NativeRead],
+    next: &'r mut usize,
+    read: Option<&'r NativeRead>,
+    pos: usize,
+    divergence: Option<Divergence>,
+}
+
+impl<'r> Player<'r> {
+    fn remaining(&mut self) -> io::Result<&'r [u8]> {
+        if let Some(divergence) = &self.divergence {
+            return Err(io::Error::other(divergence.clone()));
+        }
+
+        let read = match self.read {
+            Some(read) => read,
+            None => {
+                let read = match self.reads.get(*self.next) {
+                    Some(read) if read.native == self.name => read,
+                    Some(read) => {
+                        return self.diverge(Divergence::UnexpectedNative {
+                            expected: read.native.clone(),
+                            found: self.name.to_owned(),
+                        })
+                    }
+                    None => return self.diverge(Divergence::Exhausted(self.name.to_owned())),
+                };
+
+                *self.next += 1;
+                self.read = Some(read);
+                read
+            }
+        };
+
+        let remaining = &read.seen[self.pos..];
+        if remaining.is_empty() && !read.eof {
+            return self.diverge(Divergence::ReadPastRecording(self.name.to_owned()));
+        }
+
+        Ok(remaining)
+    }
+
+    fn diverge(&mut self, divergence: Divergence) -> io::Result<&'r [u8]> {
+        let err = io::Error::other(divergence.clone());
+        self.divergence = Some(divergence);
+        Err(err)
+    }
+}
+
+impl Read for Player<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.remaining()?;
+
+        let len = remaining.len().min(buf.len());
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl BufRead for Player<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.remaining()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
@@ -1,147 +1,615 @@
-use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+use std::time::Duration;
+use std::{error, fmt};
 
-use crate::op::{If, IfStarBlock, Intrinsic, OpBlock, OpType, While};
+use crate::error::InfoKind;
+use crate::native::{Native, NativeContext, NativeError, Natives};
+use crate::op::{Arity, If, IfStarBlock, Intrinsic, Op, OpBlock, OpType, OpView, While};
 use crate::program::{FileLocation, Program};
+use crate::replay::{Divergence, Input, Recording};
+use crate::token::Marker;
 use crate::{Error, Result};
 
+/// Number of values from the top of the stack shown when a simulation fails
+const STACK_SNAPSHOT_LEN: usize = 8;
+
 #[derive(Debug)]
-pub(crate) enum SimulationError {
-    StackUnderflow,
+#[non_exhaustive]
+pub enum SimulationError {
+    StackUnderflow {
+        word: String,
+        needed: usize,
+        available: usize,
+    },
+    OpLimitExceeded(u64),
+    StackLimitExceeded(usize),
+    /// The program used more than this many bytes
+    MemoryLimitExceeded(usize),
+    TimedOut(Duration),
+    Output(io::Error),
+    Native(String, NativeError),
+    NativeArityMismatch {
+        name: String,
+        expected: usize,
+        left: usize,
+    },
+    ReplayDiverged(Divergence),
+    /// The program finished with this many values left on the stack
+    LeftoverValues(usize),
+    /// The arithmetic intrinsic overflowed under [`OverflowPolicy::Trap`]
+    Overflow(Intrinsic),
+    DivisionByZero,
 }
 
 impl fmt::Display for SimulationError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use SimulationError::*;
         match self {
-            StackUnderflow => write!(f, "Stack underflowed"),
+            StackUnderflow {
+                word,
+                needed,
+                available,
+            } => write!(
+                f,
+                "`{}` needs {} value{} but the stack has {}",
+                word,
+                needed,
+                if *needed == 1 { "" } else { "s" },
+                available
+            ),
+            OpLimitExceeded(limit) => write!(f, "Executed more than {} operations", limit),
+            StackLimitExceeded(limit) => write!(f, "Stack grew beyond {} values", limit),
+            MemoryLimitExceeded(limit) => write!(f, "Used more than {} bytes of memory", limit),
+            TimedOut(limit) => write!(f, "Simulation ran for longer than {:?}", limit),
+            Output(err) => write!(f, "Failed to write output: {}", err),
+            Native(name, err) => write!(f, "`{}` failed: {}", name, err),
+            NativeArityMismatch {
+                name,
+                expected,
+                left,
+            } => write!(
+                f,
+                "`{}` should leave {} value{} in place of its inputs, but left {}",
+                name,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                left
+            ),
+            ReplayDiverged(divergence) => write!(f, "Replay diverged: {}", divergence),
+            LeftoverValues(count) => write!(
+                f,
+                "Program finished with {} value{} left on the stack",
+                count,
+                if *count == 1 { "" } else { "s" }
+            ),
+            Overflow(intr) => write!(f, "Operation `{}` overflowed", intr),
+            DivisionByZero => write!(f, "Division by zero"),
         }
     }
 }
 
-struct Stack(Vec<u64>);
+impl error::Error for SimulationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Output(err) => Some(err),
+            Self::Native(_, err) => Some(err.as_ref()),
+            Self::ReplayDiverged(divergence) => Some(divergence),
+            _ => None,
+        }
+    }
+}
 
-impl Stack {
-    fn new() -> Self {
-        Self(Vec::new())
+/// What happens when `+`, `-` or `*` overflows 64 bits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OverflowPolicy {
+    /// Wrap around silently
+    Wrap,
+    /// Wrap around and log a warning
+    #[default]
+    Warn,
+    /// Fail with [`SimulationError::Overflow`]
+    Trap,
+}
+
+impl OverflowPolicy {
+    /// Applies the policy to an overflow of `intrinsic` at `loc`, failing
+    /// unless the wrapped result should be used
+    fn check(self, intrinsic: Intrinsic, loc: &FileLocation) -> Result<()> {
+        match self {
+            Self::Wrap => Ok(()),
+            Self::Warn => {
+                log::warn!("<-- {} --> Operation `{}` overflowed", loc, intrinsic);
+                Ok(())
+            }
+            Self::Trap => Err(Error::from(SimulationError::Overflow(intrinsic))),
+        }
     }
+}
+
+pub(crate) struct Stack(pub(crate) Vec<u64>);
 
-    fn push(&mut self, val: u64) {
+impl Stack {
+    pub(crate) fn push(&mut self, val: u64) {
         self.0.push(val);
     }
 
-    fn pop(&mut self) -> Result<u64> {
-        match self.0.pop() {
-            Some(val) => Ok(val),
-            None => Err(Error::from(SimulationError::StackUnderflow)),
+    /// Checks that `word` can pop `needed` values off the stack
+    pub(crate) fn require(&self, word: &str, needed: usize) -> Result<()> {
+        if self.0.len() < needed {
+            Err(Error::from(SimulationError::StackUnderflow {
+                word: word.to_owned(),
+                needed,
+                available: self.0.len(),
+            }))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Pops the top value, which must have been checked with [`Stack::require`]
+    pub(crate) fn pop(&mut self) -> u64 {
+        self.0
+            .pop()
+            .expect("stack depth should be checked before popping")
+    }
+
+    fn snapshot(&self) -> InfoKind {
+        let start = self.0.len().saturating_sub(STACK_SNAPSHOT_LEN);
+
+        InfoKind::StackSnapshot {
+            top: self.0[start..].to_vec(),
+            len: self.0.len(),
+        }
+    }
+}
+
+/// Attaches `loc` and a snapshot of the stack to `err`, unless an inner
+/// operation has already done so
+pub(crate) fn locate_error(err: Error, stack: &Stack, loc: &FileLocation) -> Error {
+    if err.has_loc() {
+        err
+    } else {
+        err.add_loc(loc.clone()).push_info(stack.snapshot(), loc.clone())
+    }
+}
+
+/// Receives events from a running simulation
+pub trait Observer {
+    /// Called right before `op` is executed with the current stack, top last.
+    /// Returning an error stops the simulation.
+    fn before_op(&mut self, _op: &OpView<'_>, _stack: &[u64]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called right after `op` has been executed, including all of the blocks
+    /// nested within it
+    fn after_op(&mut self, _op: &OpView<'_>, _stack: &[u64]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once the condition guarding a block has been popped, with
+    /// whether the block is entered. `marker` is the `if`, `if*` or `do`
+    /// preceding the block, located at `loc`.
+    fn on_branch(&mut self, _marker: Marker, _loc: &FileLocation, _taken: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Observer for () {}
+
+/// Forwards every event to each observer in turn
+impl Observer for Vec<&mut dyn Observer> {
+    fn before_op(&mut self, op: &OpView<'_>, stack: &[u64]) -> Result<()> {
+        self.iter_mut()
+            .try_for_each(|observer| observer.before_op(op, stack))
+    }
+
+    fn after_op(&mut self, op: &OpView<'_>, stack: &[u64]) -> Result<()> {
+        self.iter_mut()
+            .try_for_each(|observer| observer.after_op(op, stack))
+    }
+
+    fn on_branch(&mut self, marker: Marker, loc: &FileLocation, taken: bool) -> Result<()> {
+        self.iter_mut()
+            .try_for_each(|observer| observer.on_branch(marker, loc, taken))
+    }
+}
+
+struct Context<'a, 'i> {
+    stack: Stack,
+    observer: &'a mut dyn Observer,
+    natives: &'a Natives,
+    output: &'a mut dyn Write,
+    input: &'a mut Input<'i>,
+    overflow: OverflowPolicy,
+    depth: usize,
+    /// Set once `exit` runs, to unwind out of every block
+    exit_code: Option<i32>,
+}
+
+/// Result of a simulation that ran to completion
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Outcome {
+    /// Values left on the stack, top last
+    pub stack: Vec<u64>,
+    /// Code the program gave to `exit`, if it stopped through it
+    pub exit_code: Option<i32>,
+}
+
+/// Simulates a [`Program`] with configurable output and starting stack.
+///
+/// ```
+/// # fn main() -> porrs::Result<()> {
+/// let program = porrs::Program::from_source("<example>", "34 35 + print")?;
+///
+/// let mut output = Vec::new();
+/// let outcome = porrs::Simulator::new(&program)
+///     .with_output(&mut output)
+///     .with_stack(vec![1, 2])
+///     .run()?;
+///
+/// assert_eq!(outcome.stack, [1, 2]);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Simulator<'a> {
+    program: &'a Program,
+    stack: Vec<u64>,
+    output: Box<dyn Write + 'a>,
+    input: Input<'a>,
+    overflow: OverflowPolicy,
+    observer: Option<&'a mut dyn Observer>,
+}
+
+impl<'a> Simulator<'a> {
+    /// Prepares to simulate `program` on an empty stack, printing to stdout and
+    /// reading from stdin
+    pub fn new(program: &'a Program) -> Self {
+        Self {
+            program,
+            stack: Vec::new(),
+            output: Box::new(io::stdout()),
+            input: Input::stdin(),
+            overflow: OverflowPolicy::default(),
+            observer: None,
+        }
+    }
+
+    /// Sends everything the program prints to `output`
+    pub fn with_output(mut self, output: impl Write + 'a) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Makes `input` available to [`Natives`] through
+    /// [`NativeContext::input`](crate::NativeContext::input)
+    pub fn with_input(mut self, input: impl Read + 'a) -> Self {
+        self.input.set_source(BufReader::new(input));
+        self
+    }
+
+    /// Records everything natives read from the input, available from
+    /// [`recording`](Simulator::recording) as the program runs
+    pub fn record_input(mut self) -> Self {
+        self.input.record();
+        self
+    }
+
+    /// Feeds natives exactly what they read in `recording` instead of the
+    /// input. The simulation fails with
+    /// [`SimulationError::ReplayDiverged`] once the program asks for
+    /// different input than was recorded.
+    pub fn with_replay(mut self, recording: Recording) -> Self {
+        self.input.replay(recording);
+        self
+    }
+
+    /// Input read so far, after [`record_input`](Simulator::record_input)
+    pub fn recording(&self) -> Option<&Recording> {
+        self.input.recording()
+    }
+
+    /// Starts the program with the values in `stack`, top last
+    pub fn with_stack(mut self, stack: Vec<u64>) -> Self {
+        self.stack = stack;
+        self
+    }
+
+    /// Sets what happens when arithmetic overflows, warning by default
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Reports the progress of the simulation to `observer`
+    pub fn with_observer(mut self, observer: &'a mut dyn Observer) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Values currently on the stack, top last. After a failed [`run`], these
+    /// are the values at the time of the failure.
+    ///
+    /// [`run`]: Simulator::run
+    pub fn stack(&self) -> &[u64] {
+        &self.stack
+    }
+
+    /// Runs the program to completion, moving the final stack into the returned
+    /// [`Outcome`]
+    pub fn run(&mut self) -> Result<Outcome> {
+        let mut no_observer = ();
+        let observer: &mut dyn Observer = match self.observer.as_deref_mut() {
+            Some(observer) => observer,
+            None => &mut no_observer,
+        };
+
+        let mut ctx = Context {
+            stack: Stack(std::mem::take(&mut self.stack)),
+            observer,
+            natives: &self.program.natives,
+            output: &mut self.output,
+            input: &mut self.input,
+            overflow: self.overflow,
+            depth: 0,
+            exit_code: None,
+        };
+
+        let result = simulate_op_block(&mut ctx, &self.program.root_block).and_then(|()| {
+            ctx.input.finish()?;
+            ctx.output
+                .flush()
+                .map_err(|err| Error::from(SimulationError::Output(err)))
+        });
+
+        match result {
+            Ok(()) => Ok(Outcome {
+                stack: ctx.stack.0,
+                exit_code: ctx.exit_code,
+            }),
+            Err(err) => {
+                self.stack = ctx.stack.0;
+                Err(err)
+            }
         }
     }
 }
 
 pub fn simulate(program: &Program) -> Result<()> {
-    let mut stack = Stack::new();
+    Simulator::new(program).run().map(|_| ())
+}
 
-    simulate_op_block(&mut stack, &program.root_block)
+/// Simulates `program`, reporting its progress to `observer`
+pub fn simulate_with(program: &Program, observer: &mut dyn Observer) -> Result<()> {
+    Simulator::new(program)
+        .with_observer(observer)
+        .run()
+        .map(|_| ())
 }
 
-fn simulate_op_block(stack: &mut Stack, op_block: &OpBlock) -> Result<()> {
+fn simulate_op_block(ctx: &mut Context, op_block: &OpBlock) -> Result<()> {
     for op in op_block.iter() {
-        let result: Result<()> = match &op.typ {
-            OpType::PushInt(val) => Ok(stack.push(*val)),
-            OpType::Intrinsic(intr) => simulate_intrinsic(stack, &intr, &op.loc),
-            OpType::If(if_op) => simulate_if(stack, if_op, &op.loc),
-            OpType::While(while_op) => simulate_while(stack, while_op),
+        let view = OpView {
+            id: op.id,
+            kind: op.kind(),
+            loc: &op.loc,
+            depth: ctx.depth,
         };
 
-        if let Err(err) = result {
-            if err.has_loc() {
-                return Err(err);
-            } else {
-                return Err(err.add_loc(op.loc.clone()));
-            }
+        let result = ctx
+            .observer
+            .before_op(&view, &ctx.stack.0)
+            .and_then(|()| simulate_op(ctx, op))
+            .and_then(|()| ctx.observer.after_op(&view, &ctx.stack.0));
+
+        result.map_err(|err| locate_error(err, &ctx.stack, &op.loc))?;
+
+        if ctx.exit_code.is_some() {
+            break;
         }
     }
 
     Ok(())
 }
 
-fn simulate_intrinsic(stack: &mut Stack, intrinsic: &Intrinsic, loc: &FileLocation) -> Result<()> {
+fn simulate_op(ctx: &mut Context, op: &Op) -> Result<()> {
+    match &op.typ {
+        OpType::PushInt(val) => {
+            ctx.stack.push(*val);
+            Ok(())
+        }
+
+        OpType::Intrinsic(Intrinsic::Exit) => {
+            ctx.exit_code = Some(simulate_exit(&mut ctx.stack)?);
+            Ok(())
+        }
+
+        OpType::Intrinsic(intr) => {
+            simulate_intrinsic(&mut ctx.stack, ctx.output, intr, ctx.overflow, &op.loc)
+        }
+
+        OpType::Native(id, _) => simulate_native(
+            &mut ctx.stack,
+            ctx.natives.get(*id),
+            ctx.output,
+            ctx.input,
+        ),
+
+        OpType::If(if_op) => {
+            ctx.depth += 1;
+            let result = simulate_if(ctx, if_op, &op.loc);
+            ctx.depth -= 1;
+
+            result
+        }
+
+        OpType::While(while_op) => {
+            ctx.depth += 1;
+            let result = simulate_while(ctx, while_op, &op.loc);
+            ctx.depth -= 1;
+
+            result
+        }
+    }
+}
+
+pub(crate) fn simulate_native(
+    stack: &mut Stack,
+    native: &Native,
+    output: &mut dyn Write,
+    input: &mut Input<'_>,
+) -> Result<()> {
+    let Arity { inputs, outputs } = native.arity;
+    stack.require(&native.name, inputs)?;
+
+    let base = stack.0.len() - inputs;
+
+    let result = input.call(&native.name, |input| {
+        let mut native_ctx = NativeContext {
+            name: &native.name,
+            stack: &mut stack.0,
+            output,
+            input,
+        };
+
+        (native.func)(&mut native_ctx)
+    })?;
+
+    result.map_err(|err| match err.downcast::<Error>() {
+        Ok(err) => *err,
+        Err(err) => Error::from(SimulationError::Native(native.name.clone(), err)),
+    })?;
+
+    let len = stack.0.len();
+    if len < base || len - base != outputs {
+        return Err(Error::from(SimulationError::NativeArityMismatch {
+            name: native.name.clone(),
+            expected: outputs,
+            left: len.saturating_sub(base),
+        }));
+    }
+
+    Ok(())
+}
+
+/// Pops the code `exit` stops the program with. Only its low 8 bits are
+/// kept, as with the exit status of a process on Linux.
+pub(crate) fn simulate_exit(stack: &mut Stack) -> Result<i32> {
+    stack.require(Intrinsic::Exit.as_str(), 1)?;
+    Ok((stack.pop() & 0xFF) as i32)
+}
+
+/// Executes every intrinsic except `exit`, which has to stop the program and
+/// is left to [`simulate_exit`]
+pub(crate) fn simulate_intrinsic(
+    stack: &mut Stack,
+    output: &mut dyn Write,
+    intrinsic: &Intrinsic,
+    overflow: OverflowPolicy,
+    loc: &FileLocation,
+) -> Result<()> {
+    stack.require(intrinsic.as_str(), intrinsic.arity().inputs)?;
+
     match intrinsic {
         Intrinsic::Dup => {
-            let a = stack.pop()?;
+            let a = stack.pop();
             stack.push(a);
             stack.push(a);
         }
 
         Intrinsic::Swap => {
-            let b = stack.pop()?;
-            let a = stack.pop()?;
+            let b = stack.pop();
+            let a = stack.pop();
             stack.push(b);
             stack.push(a);
         }
 
         Intrinsic::Drop => {
-            let _ = stack.pop()?;
+            let _ = stack.pop();
         }
 
         Intrinsic::Print => {
-            println!("{val} ({val:#018x})", val = stack.pop()?);
+            writeln!(output, "{val} ({val:#018x})", val = stack.pop())
+                .map_err(|err| Error::from(SimulationError::Output(err)))?;
         }
 
         Intrinsic::Over => {
-            let b = stack.pop()?;
-            let a = stack.pop()?;
+            let b = stack.pop();
+            let a = stack.pop();
             stack.push(a);
             stack.push(b);
             stack.push(a);
         }
 
         Intrinsic::Rot => {
-            let c = stack.pop()?;
-            let b = stack.pop()?;
-            let a = stack.pop()?;
+            let c = stack.pop();
+            let b = stack.pop();
+            let a = stack.pop();
             stack.push(b);
             stack.push(c);
             stack.push(a);
         }
 
-        Intrinsic::Plus => {
-            let b = stack.pop()?;
-            let a = stack.pop()?;
-            let result = a.checked_add(b).unwrap_or_else(|| {
-                log::warn!("<-- {} --> Operation `+` overflowed", loc);
-                a.wrapping_add(b)
-            });
-            stack.push(result);
-        }
-
-        Intrinsic::Subtract => {
-            let b = stack.pop()?;
-            let a = stack.pop()?;
-            let result = a.checked_sub(b).unwrap_or_else(|| {
-                log::warn!("<-- {} --> Operation `-` overflowed", loc);
-                a.wrapping_sub(b)
-            });
+        Intrinsic::Plus | Intrinsic::Subtract | Intrinsic::Multiply => {
+            let b = stack.pop();
+            let a = stack.pop();
+            let (result, overflowed) = match intrinsic {
+                Intrinsic::Plus => a.overflowing_add(b),
+                Intrinsic::Subtract => a.overflowing_sub(b),
+                _ => a.overflowing_mul(b),
+            };
+
+            if overflowed {
+                if let Err(err) = overflow.check(*intrinsic, loc) {
+                    stack.push(a);
+                    stack.push(b);
+                    return Err(err);
+                }
+            }
             stack.push(result);
         }
 
-        Intrinsic::Multiply => {
-            let b = stack.pop()?;
-            let a = stack.pop()?;
-            stack.push(a * b);
-        }
-
         Intrinsic::DivMod => {
-            let b = stack.pop()?;
-            let a = stack.pop()?;
+            let b = stack.pop();
+            let a = stack.pop();
+            if b == 0 {
+                stack.push(a);
+                stack.push(b);
+                return Err(Error::from(SimulationError::DivisionByZero));
+            }
             stack.push(a / b);
             stack.push(a % b);
         }
+
+        Intrinsic::Exit => unreachable!("`exit` is executed by `simulate_exit`"),
     }
 
     Ok(())
 }
 
-fn is_condition_true(cond: u64, loc: &FileLocation) -> bool {
+fn pop_condition(stack: &mut Stack, marker: Marker, loc: &FileLocation) -> Result<u64> {
+    stack
+        .require(marker.as_str(), 1)
+        .map_err(|err| locate_error(err, stack, loc))?;
+
+    Ok(stack.pop())
+}
+
+/// Pops the condition guarding the block after `marker`, returning whether the
+/// block should be entered
+fn branch(ctx: &mut Context, marker: Marker, loc: &FileLocation) -> Result<bool> {
+    let taken = is_condition_true(pop_condition(&mut ctx.stack, marker, loc)?, loc);
+
+    ctx.observer
+        .on_branch(marker, loc, taken)
+        .map_err(|err| locate_error(err, &ctx.stack, loc))?;
+
+    Ok(taken)
+}
+
+pub(crate) fn is_condition_true(cond: u64, loc: &FileLocation) -> bool {
     if cond != 0 && cond != 1 {
         log::warn!(
             "<-- {} --> Non-binary value ({}) used as a boolean condition",
@@ -153,38 +621,58 @@ fn is_condition_true(cond: u64, loc: &FileLocation) -> bool {
     cond > 0
 }
 
-fn simulate_if(stack: &mut Stack, if_op: &If, if_loc: &FileLocation) -> Result<()> {
-    if is_condition_true(stack.pop()?, if_loc) {
-        simulate_op_block(stack, &if_op.if_block)
+fn simulate_if(ctx: &mut Context, if_op: &If, if_loc: &FileLocation) -> Result<()> {
+    let nested = |ctx: &mut Context, block| simulate_nested(ctx, block, Marker::If, if_loc);
+
+    if branch(ctx, Marker::If, if_loc)? {
+        nested(ctx, &if_op.if_block)
     } else {
         for IfStarBlock { loc, cond, inner } in &if_op.if_star_blocks {
-            simulate_op_block(stack, cond)?;
+            nested(ctx, cond)?;
+            if ctx.exit_code.is_some() {
+                return Ok(());
+            }
 
-            if is_condition_true(stack.pop()?, loc) {
-                return simulate_op_block(stack, inner);
+            if branch(ctx, Marker::IfStar, loc)? {
+                return nested(ctx, inner);
             }
         }
 
         if let Some(else_block) = &if_op.else_block {
-            simulate_op_block(stack, else_block)
+            nested(ctx, else_block)
         } else {
             Ok(())
         }
     }
 }
 
-fn simulate_while(stack: &mut Stack, while_op: &While) -> Result<()> {
-    let do_loc = while_op.do_loc.as_ref().unwrap();
+fn simulate_while(ctx: &mut Context, while_op: &While, while_loc: &FileLocation) -> Result<()> {
+    let do_loc = while_op.do_loc.as_ref().unwrap_or(while_loc);
 
-    simulate_op_block(stack, &while_op.cond_block)?;
+    let nested = |ctx: &mut Context, block| simulate_nested(ctx, block, Marker::While, while_loc);
 
-    while is_condition_true(
-        stack.pop().map_err(|err| err.add_loc(do_loc.clone()))?,
-        do_loc,
-    ) {
-        simulate_op_block(stack, &while_op.do_block)?;
-        simulate_op_block(stack, &while_op.cond_block)?;
+    nested(ctx, &while_op.cond_block)?;
+
+    while ctx.exit_code.is_none() && branch(ctx, Marker::Do, do_loc)? {
+        nested(ctx, &while_op.do_block)?;
+        if ctx.exit_code.is_some() {
+            break;
+        }
+        nested(ctx, &while_op.cond_block)?;
     }
 
     Ok(())
 }
+
+/// Simulates a block nested in the `if` or `while` at `loc`, noting it on
+/// errors. Errors of the block's own conditions are located at their markers
+/// instead.
+fn simulate_nested(
+    ctx: &mut Context,
+    op_block: &OpBlock,
+    marker: Marker,
+    loc: &FileLocation,
+) -> Result<()> {
+    simulate_op_block(ctx, op_block)
+        .map_err(|err| err.push_info(InfoKind::InBlock(marker), loc.clone()))
+}
@@ -0,0 +1,210 @@
+use std::fs;
+use std::path::Path;
+
+use crate::bytecode::{Addr, Bytecode, Instr};
+use crate::porbc::{Decoder, LoadingError};
+use crate::program::FileLocation;
+use crate::{Error, Result};
+
+/// Version of the snapshot format, bumped on any incompatible change
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+/// Bytes every snapshot file starts with
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"PORSNAP\0";
+
+/// State of a paused [`Vm`](crate::Vm), from which it can be resumed later.
+///
+/// The stack is the only state a program has, so together with the position
+/// of the next instruction it is enough to resume the program exactly.
+/// Snapshots remember a fingerprint of the program they were taken of and
+/// can only be resumed with the same program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub(crate) fingerprint: u64,
+    pub(crate) pc: Addr,
+    pub(crate) executed: u64,
+    pub(crate) stack: Vec<u64>,
+}
+
+impl Snapshot {
+    /// Number of instructions executed before the snapshot was taken
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Values on the stack, top last
+    pub fn stack(&self) -> &[u64] {
+        &self.stack
+    }
+
+    /// Encodes the snapshot with all integers in little-endian byte order:
+    ///
+    /// ```text
+    /// magic        "PORSNAP\0"
+    /// version      u16, SNAPSHOT_VERSION
+    /// fingerprint  u64, of the program
+    /// position     u64, index of the next instruction
+    /// executed     u64, instructions executed so far
+    /// stack        u64 count, then u64 values, top last
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(SNAPSHOT_MAGIC.len() + 2 + 8 * (4 + self.stack.len()));
+
+        buf.extend(SNAPSHOT_MAGIC);
+        buf.extend(SNAPSHOT_VERSION.to_le_bytes());
+        buf.extend(self.fingerprint.to_le_bytes());
+        buf.extend((self.pc as u64).to_le_bytes());
+        buf.extend(self.executed.to_le_bytes());
+        buf.extend((self.stack.len() as u64).to_le_bytes());
+        for val in &self.stack {
+            buf.extend(val.to_le_bytes());
+        }
+
+        buf
+    }
+
+    /// Decodes a snapshot in the format described in
+    /// [`to_bytes`](Snapshot::to_bytes)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut dec = Decoder {
+            bytes,
+            section: "header",
+        };
+
+        if dec.take(SNAPSHOT_MAGIC.len()).ok() != Some(&SNAPSHOT_MAGIC[..]) {
+            return Err(Error::from(LoadingError::NotSnapshot));
+        }
+
+        let version = dec.u16()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::from(LoadingError::UnsupportedVersion {
+                found: version,
+                expected: SNAPSHOT_VERSION,
+            }));
+        }
+
+        let fingerprint = dec.u64()?;
+        let pc = dec.u64()? as Addr;
+        let executed = dec.u64()?;
+
+        dec.section = "stack";
+        let len = dec.u64()?;
+        let mut stack = Vec::new();
+        for _ in 0..len {
+            stack.push(dec.u64()?);
+        }
+
+        if !dec.bytes.is_empty() {
+            return Err(Error::from(LoadingError::TrailingBytes(dec.bytes.len())));
+        }
+
+        Ok(Self {
+            fingerprint,
+            pc,
+            executed,
+            stack,
+        })
+    }
+
+    /// Loads the snapshot file at `path`
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let bytes = fs::read(path)
+            .map_err(|err| Error::from(LoadingError::FileIo(path.to_path_buf(), err)))?;
+
+        Self::from_bytes(&bytes).map_err(|err| err.add_loc(FileLocation::from_path(path)))
+    }
+
+    /// Checks that the snapshot can be resumed with `bytecode`
+    pub(crate) fn check(&self, bytecode: &Bytecode) -> Result<()> {
+        if self.fingerprint != bytecode.fingerprint() {
+            return Err(Error::from(LoadingError::SnapshotMismatch));
+        }
+
+        if self.pc > bytecode.len() {
+            return Err(Error::from(LoadingError::OutOfRange {
+                what: "instruction",
+                index: self.pc.try_into().unwrap_or(u32::MAX),
+                len: bytecode.len(),
+            }));
+        }
+
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a hash
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn u64(&mut self, val: u64) {
+        self.bytes(&val.to_le_bytes());
+    }
+
+    fn str(&mut self, text: &str) {
+        self.u64(text.len() as u64);
+        self.bytes(text.as_bytes());
+    }
+
+    fn index(&mut self, index: Option<usize>) {
+        self.u64(index.map_or(u64::MAX, |index| index as u64));
+    }
+}
+
+impl Bytecode {
+    /// Hash of the instructions, the blocks they are nested in and the natives
+    /// they call, identifying the program. Locations are left out, so that the
+    /// same program is recognized whichever path it was loaded from.
+    pub(crate) fn fingerprint(&self) -> u64 {
+        let mut hash = Fnv1a::new();
+
+        for block in &self.blocks {
+            hash.str(block.marker.as_str());
+            hash.index(block.parent);
+        }
+
+        for (instr, parent) in self.instrs.iter().zip(&self.parents) {
+            match instr {
+                Instr::PushInt(val) => {
+                    hash.u64(0);
+                    hash.u64(*val);
+                }
+                Instr::Intrinsic(intr) => {
+                    hash.u64(1);
+                    hash.str(intr.as_str());
+                }
+                Instr::Native(id) => {
+                    let native = self.natives.get(*id);
+                    hash.u64(2);
+                    hash.str(&native.name);
+                    hash.u64(native.arity.inputs as u64);
+                    hash.u64(native.arity.outputs as u64);
+                }
+                Instr::Branch { marker, target } => {
+                    hash.u64(3);
+                    hash.str(marker.as_str());
+                    hash.index(Some(*target));
+                }
+                Instr::Jump(target) => {
+                    hash.u64(4);
+                    hash.index(Some(*target));
+                }
+            }
+
+            hash.index(*parent);
+        }
+
+        hash.0
+    }
+}
@@ -0,0 +1,145 @@
+/// Words from other Forth dialects that have no direct counterpart in Porth,
+/// together with a hint on how to express them instead.
+const FORTH_HINTS: &[(&str, &str)] = &[
+    (".", "use `print` to pop and print the top of the stack"),
+    (
+        ".s",
+        "the stack cannot be printed without consuming it, use `dup print` to inspect the top value",
+    ),
+    (
+        "emit",
+        "character output is not supported, use `print` to print the value as an integer",
+    ),
+    ("2dup", "use `over over` to duplicate the top two values"),
+    ("2drop", "use `drop drop` to drop the top two values"),
+    ("nip", "use `swap drop` to drop the second value"),
+    ("tuck", "use `swap over` to copy the top value below the second"),
+    ("/", "use `divmod` and `drop` the remainder"),
+    ("mod", "use `divmod` and `swap drop` the quotient"),
+    ("/mod", "use `divmod`, which leaves the remainder on top"),
+    ("then", "close the block with `end`"),
+    ("begin", "loops are written as `while <condition> do <body> end`"),
+    ("repeat", "close the loop with `end`"),
+];
+
+/// Returns a hint for `word` if it is a well-known word from another Forth
+pub(crate) fn forth_hint(word: &str) -> Option<&'static str> {
+    FORTH_HINTS
+        .iter()
+        .find(|(text, _)| text.eq_ignore_ascii_case(word))
+        .map(|(_, hint)| *hint)
+}
+
+/// Returns the candidates closest to `word` by edit distance, best first.
+///
+/// Only candidates within a third of the length of `word` (but at least one
+/// edit) are considered similar enough to be suggested, and never when every
+/// character would have to change.
+pub(crate) fn similar_words<'a>(
+    word: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Vec<&'a str> {
+    let len = word.chars().count();
+    let max_distance = (len / 3).max(1).min(len.saturating_sub(1));
+
+    let mut similar = candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect::<Vec<_>>();
+
+    similar.sort();
+    similar.dedup();
+
+    let best = similar.first().map(|(distance, _)| *distance);
+    similar
+        .into_iter()
+        .take_while(|(distance, _)| Some(*distance) == best)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Optimal string alignment distance, i.e. Levenshtein distance where
+/// swapping two adjacent characters counts as a single edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+
+            rows[i][j] = distance;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORDS: &[&str] = &["dup", "drop", "swap", "over", "print", "divmod", "while"];
+
+    fn similar(word: &str) -> Vec<&'static str> {
+        similar_words(word, WORDS.iter().copied())
+    }
+
+    #[test]
+    fn transpositions_are_a_single_edit() {
+        assert_eq!(edit_distance("dpu", "dup"), 1);
+        assert_eq!(edit_distance("pirnt", "print"), 1);
+        assert_eq!(similar("dpu"), ["dup"]);
+    }
+
+    #[test]
+    fn allows_an_edit_per_three_characters() {
+        // Words shorter than six characters still allow a single edit
+        assert_eq!(similar("dyp"), ["dup"]);
+        assert_eq!(similar("prnt"), ["print"]);
+        assert!(similar("pxnnt").is_empty());
+
+        // Two edits need a word of at least six characters
+        assert_eq!(edit_distance("dibmid", "divmod"), 2);
+        assert_eq!(similar("dibmid"), ["divmod"]);
+        assert!(similar("dibmidxxx").is_empty());
+    }
+
+    #[test]
+    fn only_suggests_the_closest_words() {
+        assert_eq!(similar("drup"), ["drop", "dup"]);
+        assert_eq!(similar("drap"), ["drop"]);
+    }
+
+    #[test]
+    fn never_suggests_for_single_characters() {
+        assert!(similar("d").is_empty());
+        assert!(similar_words("+", ["-", "*", "="]).is_empty());
+    }
+
+    #[test]
+    fn hints_at_forth_words() {
+        assert!(forth_hint("2dup").unwrap().contains("over over"));
+        assert!(forth_hint("THEN").unwrap().contains("end"));
+        assert_eq!(forth_hint("dup"), None);
+        assert_eq!(forth_hint("2swap"), None);
+    }
+}
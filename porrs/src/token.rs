@@ -38,8 +38,10 @@ impl Word {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub(crate) enum Marker {
+/// Keywords delimiting blocks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Marker {
     If,
     IfStar,
     Else,
@@ -49,6 +51,15 @@ pub(crate) enum Marker {
 }
 
 impl Marker {
+    pub const ALL: [Self; 6] = [
+        Self::If,
+        Self::IfStar,
+        Self::Else,
+        Self::While,
+        Self::Do,
+        Self::End,
+    ];
+
     const IF_TEXT: &'static str = "if";
     const IF_STAR_TEXT: &'static str = "if*";
     const ELSE_TEXT: &'static str = "else";
@@ -56,7 +67,7 @@ impl Marker {
     const DO_TEXT: &'static str = "do";
     const END_TEXT: &'static str = "end";
 
-    const fn as_str(&self) -> &'static str {
+    pub const fn as_str(&self) -> &'static str {
         match self {
             Self::If => Self::IF_TEXT,
             Self::IfStar => Self::IF_STAR_TEXT,
@@ -68,7 +79,8 @@ impl Marker {
     }
 }
 
-pub(crate) struct InvalidMarkerError;
+#[derive(Debug)]
+pub struct InvalidMarkerError;
 
 impl TryFrom<&str> for Marker {
     type Error = InvalidMarkerError;
@@ -0,0 +1,148 @@
+use std::io::{self, Write};
+
+use crate::op::OpView;
+use crate::program::FileLocation;
+use crate::simulate::Observer;
+use crate::token::Marker;
+use crate::Result;
+
+/// Version of the trace format, bumped on any incompatible change
+pub const TRACE_VERSION: u32 = 1;
+
+/// Number of values from the top of the stack recorded with each operation
+pub const TRACE_STACK_TOP: usize = 4;
+
+/// An [`Observer`] writing an execution trace to `W` in the JSON Lines format.
+///
+/// The trace starts with a header line, followed by one line per event in the
+/// order they happened. Keys always appear in the order listed below and no
+/// timing information is recorded, so traces of the same program with the
+/// same input are byte-for-byte identical and can be diffed directly.
+///
+/// ```text
+/// {"event":"trace","version":1}
+/// {"event":"op","op":"dup","file":"foo.porth","line":3,"col":5,"depth":1,"stack_len":2,"stack_top":[1,2]}
+/// {"event":"branch","marker":"if","file":"foo.porth","line":2,"col":3,"taken":true}
+/// ```
+///
+/// - `op` events are written right before an operation executes. `stack_top`
+///   holds up to [`TRACE_STACK_TOP`] values from the top of the stack, top last.
+/// - `branch` events are written once the condition guarding a block has been
+///   popped. `marker` is the `if`, `if*` or `do` preceding the block.
+///
+/// `line` and `col` are `null` for locations without a position.
+///
+/// Writing stops at the first I/O error, which is returned by
+/// [`Tracer::finish`] rather than interrupting the simulation.
+pub struct Tracer<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W) -> Self {
+        let mut tracer = Self {
+            writer,
+            error: None,
+        };
+
+        tracer.write_line(|w| {
+            write!(
+                w,
+                "{{\"event\":\"trace\",\"version\":{}}}",
+                TRACE_VERSION
+            )
+        });
+
+        tracer
+    }
+
+    /// Flushes the trace, returning the writer or the first I/O error hit
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_line(&mut self, line: impl FnOnce(&mut W) -> io::Result<()>) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = line(&mut self.writer).and_then(|()| writeln!(self.writer));
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+}
+
+fn write_json_str(w: &mut impl Write, text: &str) -> io::Result<()> {
+    write!(w, "\"")?;
+
+    for c in text.chars() {
+        match c {
+            '"' => write!(w, "\\\"")?,
+            '\\' => write!(w, "\\\\")?,
+            '\n' => write!(w, "\\n")?,
+            '\r' => write!(w, "\\r")?,
+            '\t' => write!(w, "\\t")?,
+            c if c.is_control() => write!(w, "\\u{:04x}", c as u32)?,
+            c => write!(w, "{}", c)?,
+        }
+    }
+
+    write!(w, "\"")
+}
+
+fn write_json_location(w: &mut impl Write, loc: &FileLocation) -> io::Result<()> {
+    write!(w, "\"file\":")?;
+    write_json_str(w, &loc.path().display().to_string())?;
+
+    match loc.position() {
+        Some(pos) => write!(w, ",\"line\":{},\"col\":{}", pos.line(), pos.column()),
+        None => write!(w, ",\"line\":null,\"col\":null"),
+    }
+}
+
+impl<W: Write> Observer for Tracer<W> {
+    fn before_op(&mut self, op: &OpView<'_>, stack: &[u64]) -> Result<()> {
+        let top = &stack[stack.len().saturating_sub(TRACE_STACK_TOP)..];
+
+        self.write_line(|w| {
+            write!(w, "{{\"event\":\"op\",\"op\":")?;
+            write_json_str(w, &op.kind().to_string())?;
+            write!(w, ",")?;
+            write_json_location(w, op.location())?;
+            write!(
+                w,
+                ",\"depth\":{},\"stack_len\":{},\"stack_top\":[",
+                op.depth(),
+                stack.len()
+            )?;
+            for (i, val) in top.iter().enumerate() {
+                if i > 0 {
+                    write!(w, ",")?;
+                }
+                write!(w, "{}", val)?;
+            }
+            write!(w, "]}}")
+        });
+
+        Ok(())
+    }
+
+    fn on_branch(&mut self, marker: Marker, loc: &FileLocation, taken: bool) -> Result<()> {
+        self.write_line(|w| {
+            write!(w, "{{\"event\":\"branch\",\"marker\":")?;
+            write_json_str(w, marker.as_str())?;
+            write!(w, ",")?;
+            write_json_location(w, loc)?;
+            write!(w, ",\"taken\":{}}}", taken)
+        });
+
+        Ok(())
+    }
+}
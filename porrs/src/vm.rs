@@ -0,0 +1,578 @@
+use std::collections::VecDeque;
+use std::io::{self, BufReader, Read, Write};
+use std::mem;
+
+use crate::bytecode::{Addr, Bytecode, Instr};
+use crate::error::InfoKind;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use crate::jit::Jit;
+use crate::limits::Limits;
+use crate::op::{Intrinsic, OpKind, OpView};
+use crate::replay::{Input, Recording};
+use crate::simulate::{
+    is_condition_true, locate_error, simulate_exit, simulate_intrinsic, simulate_native, Outcome,
+    OverflowPolicy, SimulationError, Stack,
+};
+use crate::snapshot::Snapshot;
+use crate::token::Marker;
+use crate::{Error, Result};
+
+/// Runs [`Bytecode`] in a single dispatch loop, producing the same output,
+/// final stack and errors as the [`Simulator`](crate::Simulator) without
+/// recursing into nested blocks.
+///
+/// ```
+/// # fn main() -> porrs::Result<()> {
+/// let program = porrs::Program::from_source("<example>", "3 while dup do 1 - end")?;
+/// let bytecode = porrs::Bytecode::from_program(&program);
+///
+/// assert_eq!(porrs::Vm::new(&bytecode).run()?.stack, [0]);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Vm<'a> {
+    bytecode: &'a Bytecode,
+    stack: Vec<u64>,
+    output: Box<dyn Write + 'a>,
+    input: Input<'a>,
+    overflow: OverflowPolicy,
+    pc: Addr,
+    executed: u64,
+    /// Code the program stopped with, once `exit` ran
+    exit_code: Option<i32>,
+    history: Option<History>,
+    limits: Option<Limits>,
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    jit: Option<&'a Jit<'a>>,
+}
+
+/// Instruction recorded so it can be undone by [`Vm::step_back`]
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    pc: Addr,
+    /// Stack length below which the instruction left the stack untouched
+    base: usize,
+    /// Number of values above `base` before the instruction, saved at the end
+    /// of [`History::values`]
+    saved: usize,
+}
+
+/// Bounded undo log of the most recently executed instructions
+#[derive(Debug)]
+struct History {
+    depth: usize,
+    entries: VecDeque<Entry>,
+    values: VecDeque<u64>,
+}
+
+impl History {
+    fn record(&mut self, instr: &Instr, pc: Addr, stack: &[u64]) {
+        if self.depth == 0 {
+            return;
+        }
+
+        let touched = match instr {
+            Instr::PushInt(_) | Instr::Jump(_) => 0,
+            Instr::Intrinsic(intr) => intr.arity().inputs,
+            Instr::Branch { .. } => 1,
+            // Natives get the whole stack, so nothing below the top is safe
+            Instr::Native(_) => stack.len(),
+        };
+        let base = stack.len().saturating_sub(touched);
+
+        self.values.extend(&stack[base..]);
+        self.entries.push_back(Entry {
+            pc,
+            base,
+            saved: stack.len() - base,
+        });
+
+        if self.entries.len() > self.depth {
+            let oldest = self
+                .entries
+                .pop_front()
+                .expect("history should not be empty");
+            self.values.drain(..oldest.saved);
+        }
+    }
+
+    /// Restores the stack from before the last recorded instruction, returning
+    /// its address
+    fn undo(&mut self, stack: &mut Vec<u64>) -> Option<Addr> {
+        let entry = self.entries.pop_back()?;
+
+        stack.truncate(entry.base);
+        stack.extend(self.values.drain(self.values.len() - entry.saved..));
+
+        Some(entry.pc)
+    }
+}
+
+impl<'a> Vm<'a> {
+    /// Prepares to run `bytecode` on an empty stack, printing to stdout and
+    /// reading from stdin
+    pub fn new(bytecode: &'a Bytecode) -> Self {
+        Self {
+            bytecode,
+            stack: Vec::new(),
+            output: Box::new(io::stdout()),
+            input: Input::stdin(),
+            overflow: OverflowPolicy::default(),
+            pc: 0,
+            executed: 0,
+            exit_code: None,
+            history: None,
+            limits: None,
+            #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+            jit: None,
+        }
+    }
+
+    /// Sends everything the program prints to `output`
+    pub fn with_output(mut self, output: impl Write + 'a) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Makes `input` available to [`Natives`](crate::Natives)
+    pub fn with_input(mut self, input: impl Read + 'a) -> Self {
+        self.input.set_source(BufReader::new(input));
+        self
+    }
+
+    /// Records everything natives read from the input, available from
+    /// [`recording`](Vm::recording) as the program runs
+    pub fn record_input(mut self) -> Self {
+        self.input.record();
+        self
+    }
+
+    /// Feeds natives exactly what they read in `recording` instead of the
+    /// input. The program fails with
+    /// [`SimulationError::ReplayDiverged`] once it asks for different input
+    /// than was recorded.
+    ///
+    /// A [`Snapshot`] does not remember how far the replay got.
+    pub fn with_replay(mut self, recording: Recording) -> Self {
+        self.input.replay(recording);
+        self
+    }
+
+    /// Input read so far, after [`record_input`](Vm::record_input)
+    pub fn recording(&self) -> Option<&Recording> {
+        self.input.recording()
+    }
+
+    /// Starts the program with the values in `stack`, top last
+    pub fn with_stack(mut self, stack: Vec<u64>) -> Self {
+        self.stack = stack;
+        self
+    }
+
+    /// Sets what happens when arithmetic overflows, warning by default
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Stops the program with an error once it exceeds any of `limits`, which
+    /// count instructions rather than operations. Limits are checked for every
+    /// instruction, so [`with_jit`](Vm::with_jit) code is not used while they
+    /// are set.
+    ///
+    /// ```
+    /// # fn main() -> porrs::Result<()> {
+    /// let program = porrs::Program::from_source("<example>", "1 while dup do end")?;
+    /// let bytecode = porrs::Bytecode::from_program(&program);
+    ///
+    /// let err = porrs::Vm::new(&bytecode)
+    ///     .with_limits(porrs::Limits::new().max_ops(1000))
+    ///     .run()
+    ///     .unwrap_err();
+    ///
+    /// assert!(matches!(
+    ///     err.kind(),
+    ///     porrs::ErrorKind::Simulation(porrs::SimulationError::OpLimitExceeded(1000))
+    /// ));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Resumes the program from `snapshot`, replacing the stack
+    ///
+    /// Fails if the snapshot was taken of a different program.
+    pub fn with_snapshot(mut self, snapshot: Snapshot) -> Result<Self> {
+        snapshot.check(self.bytecode)?;
+
+        self.pc = snapshot.pc;
+        self.executed = snapshot.executed;
+        self.stack = snapshot.stack;
+        Ok(self)
+    }
+
+    /// Runs the machine code in `jit` instead of interpreting each
+    /// instruction, falling back to the interpreter for the instructions it
+    /// traps on
+    ///
+    /// # Panics
+    ///
+    /// If `jit` was not compiled from the same [`Bytecode`] as this `Vm` runs
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub fn with_jit(mut self, jit: &'a Jit<'a>) -> Self {
+        assert!(
+            std::ptr::eq(jit.bytecode(), self.bytecode),
+            "JIT code was compiled from different bytecode"
+        );
+        self.jit = Some(jit);
+        self
+    }
+
+    /// Keeps an undo log of the last `depth` instructions, so they can be
+    /// reversed one at a time with [`step_back`]
+    ///
+    /// Only the values an instruction may change are logged, since the stack
+    /// is the only state a program has. Output already printed and input
+    /// already read are not taken back. Instructions run by [`with_jit`] code
+    /// are not logged.
+    ///
+    /// [`step_back`]: Vm::step_back
+    /// [`with_jit`]: Vm::with_jit
+    pub fn with_history(mut self, depth: usize) -> Self {
+        self.history = Some(History {
+            depth,
+            entries: VecDeque::new(),
+            values: VecDeque::new(),
+        });
+        self
+    }
+
+    /// Values currently on the stack, top last. After a failed [`run`], these
+    /// are the values at the time of the failure.
+    ///
+    /// [`run`]: Vm::run
+    pub fn stack(&self) -> &[u64] {
+        &self.stack
+    }
+
+    /// Captures the current state, e.g. after [`run_until`] paused
+    ///
+    /// [`run_until`]: Vm::run_until
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            fingerprint: self.bytecode.fingerprint(),
+            pc: self.pc,
+            executed: self.executed,
+            stack: self.stack.clone(),
+        }
+    }
+
+    /// Number of instructions executed so far
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Code the program stopped with, if it called `exit` while being
+    /// [`step`](Vm::step)ped
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Whether the program ran past its last instruction
+    pub fn is_finished(&self) -> bool {
+        self.pc >= self.bytecode.len()
+    }
+
+    /// The next instruction to execute, or `None` once the program finished
+    ///
+    /// The `Branch` which starts an `if` is reported as the `if` itself.
+    pub fn op(&self) -> Option<OpView<'a>> {
+        self.op_at(self.pc)
+    }
+
+    /// The instruction [`step_back`] would return to, if any
+    ///
+    /// [`step_back`]: Vm::step_back
+    pub fn previous_op(&self) -> Option<OpView<'a>> {
+        let entry = self.history.as_ref()?.entries.back()?;
+        self.op_at(entry.pc)
+    }
+
+    fn op_at(&self, pc: Addr) -> Option<OpView<'a>> {
+        let bytecode = self.bytecode;
+        let instr = bytecode.instrs.get(pc)?;
+
+        let mut depth = 0;
+        let mut parent = bytecode.parents[pc];
+        while let Some(block) = parent.map(|id| &bytecode.blocks[id]) {
+            depth += 1;
+            parent = block.parent;
+        }
+
+        let kind = match instr {
+            Instr::PushInt(val) => OpKind::PushInt(*val),
+            Instr::Intrinsic(intr) => OpKind::Intrinsic(*intr),
+            Instr::Native(id) => OpKind::Native(&bytecode.natives.get(*id).name),
+            Instr::Branch {
+                marker: Marker::If, ..
+            } => {
+                depth -= 1;
+                OpKind::If
+            }
+            Instr::Branch { marker, .. } => OpKind::Branch(*marker),
+            Instr::Jump(_) => OpKind::Jump,
+        };
+
+        Some(OpView {
+            id: bytecode.ids[pc],
+            kind,
+            loc: &bytecode.locs[pc],
+            depth,
+        })
+    }
+
+    /// Number of instructions [`step_back`] can currently undo
+    ///
+    /// [`step_back`]: Vm::step_back
+    pub fn history_len(&self) -> usize {
+        self.history
+            .as_ref()
+            .map_or(0, |history| history.entries.len())
+    }
+
+    /// Approximate number of bytes taken by the undo log
+    pub fn history_bytes(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| {
+            history.entries.capacity() * mem::size_of::<Entry>()
+                + history.values.capacity() * mem::size_of::<u64>()
+        })
+    }
+
+    /// Executes the next instruction, or does nothing once the program
+    /// finished. The output is flushed after the last instruction.
+    ///
+    /// If the instruction fails and the `Vm` keeps a history, its effect on
+    /// the stack is undone so it can be inspected or stepped back from.
+    pub fn step(&mut self) -> Result<()> {
+        if self.is_finished() {
+            return Ok(());
+        }
+
+        let mut stack = Stack(mem::take(&mut self.stack));
+        let result = self.interpret(&mut stack);
+        self.stack = stack.0;
+        result?;
+
+        if self.is_finished() {
+            self.input.finish()?;
+            self.output
+                .flush()
+                .map_err(|err| Error::from(SimulationError::Output(err)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Undoes the last instruction recorded by [`with_history`], returning
+    /// false when there is none left
+    ///
+    /// [`with_history`]: Vm::with_history
+    pub fn step_back(&mut self) -> bool {
+        match self
+            .history
+            .as_mut()
+            .and_then(|history| history.undo(&mut self.stack))
+        {
+            Some(pc) => {
+                // Nothing runs after `exit`, so it can only be the last
+                // instruction undone
+                self.pc = pc;
+                self.executed -= 1;
+                self.exit_code = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs the program to completion, moving the final stack into the returned
+    /// [`Outcome`]
+    pub fn run(&mut self) -> Result<Outcome> {
+        let outcome = self.run_until(|_| false)?;
+        Ok(outcome.expect("a run which never pauses should finish"))
+    }
+
+    /// Runs the program until it finishes, or until `pause` returns true when
+    /// given the number of instructions executed so far. It is asked before
+    /// each instruction, except while running [`with_jit`] code.
+    ///
+    /// Returns the [`Outcome`] once the program finishes, or `None` when it
+    /// paused. Calling this again continues from where it paused.
+    ///
+    /// [`with_jit`]: Vm::with_jit
+    pub fn run_until(&mut self, pause: impl FnMut(u64) -> bool) -> Result<Option<Outcome>> {
+        let mut stack = Stack(mem::take(&mut self.stack));
+
+        let result = self.execute(&mut stack, pause).and_then(|finished| {
+            if finished {
+                self.input.finish()?;
+                self.output
+                    .flush()
+                    .map_err(|err| Error::from(SimulationError::Output(err)))?;
+            }
+            Ok(finished)
+        });
+
+        match result {
+            Ok(true) => {
+                self.pc = 0;
+                self.executed = 0;
+                if let Some(history) = &mut self.history {
+                    history.entries.clear();
+                    history.values.clear();
+                }
+
+                Ok(Some(Outcome {
+                    stack: stack.0,
+                    exit_code: self.exit_code.take(),
+                }))
+            }
+            Ok(false) => {
+                self.stack = stack.0;
+                Ok(None)
+            }
+            Err(err) => {
+                self.stack = stack.0;
+                Err(err)
+            }
+        }
+    }
+
+    /// Runs from the current instruction, returning whether the program
+    /// finished rather than paused
+    fn execute(&mut self, stack: &mut Stack, mut pause: impl FnMut(u64) -> bool) -> Result<bool> {
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        if let Some(jit) = self.jit.filter(|_| self.limits.is_none()) {
+            let pc = self.pc;
+            jit.execute(stack, pc, |stack, pc| self.step_at(stack, pc))?;
+            return Ok(true);
+        }
+
+        while self.pc < self.bytecode.len() {
+            if pause(self.executed) {
+                return Ok(false);
+            }
+
+            self.interpret(stack)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Executes the instruction at the current address within the limits,
+    /// recording it in the history if there is one
+    fn interpret(&mut self, stack: &mut Stack) -> Result<()> {
+        let pc = self.pc;
+
+        if let Some(limits) = &mut self.limits {
+            limits
+                .check_before()
+                .map_err(|err| self.backtrace(err, stack, pc))?;
+        }
+
+        if let Some(history) = &mut self.history {
+            history.record(&self.bytecode.instrs[pc], pc, &stack.0);
+        }
+
+        let result = self.step_at(stack, pc).and_then(|next| match &self.limits {
+            Some(limits) => limits
+                .check_after(&stack.0)
+                .map(|()| next)
+                .map_err(|err| self.backtrace(err, stack, pc)),
+            None => Ok(next),
+        });
+
+        match result {
+            Ok(next) => {
+                self.pc = next;
+                self.executed += 1;
+                Ok(())
+            }
+            Err(err) => {
+                if let Some(history) = &mut self.history {
+                    history.undo(&mut stack.0);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Executes the instruction at `pc`, returning the address of the next one
+    /// or the error it raised with its full backtrace
+    fn step_at(&mut self, stack: &mut Stack, pc: Addr) -> Result<Addr> {
+        self.dispatch(stack, pc)
+            .map_err(|err| self.backtrace(err, stack, pc))
+    }
+
+    fn dispatch(&mut self, stack: &mut Stack, pc: Addr) -> Result<Addr> {
+        let bytecode = self.bytecode;
+        let loc = &bytecode.locs[pc];
+
+        match &bytecode.instrs[pc] {
+            Instr::PushInt(val) => {
+                stack.push(*val);
+                Ok(pc + 1)
+            }
+
+            Instr::Intrinsic(Intrinsic::Exit) => {
+                self.exit_code = Some(simulate_exit(stack)?);
+                Ok(bytecode.len())
+            }
+
+            Instr::Intrinsic(intr) => {
+                simulate_intrinsic(stack, &mut self.output, intr, self.overflow, loc)
+                    .map(|()| pc + 1)
+            }
+
+            Instr::Native(id) => simulate_native(
+                stack,
+                bytecode.natives.get(*id),
+                &mut self.output,
+                &mut self.input,
+            )
+            .map(|()| pc + 1),
+
+            Instr::Branch { marker, target } => stack.require(marker.as_str(), 1).map(|()| {
+                if is_condition_true(stack.pop(), loc) {
+                    pc + 1
+                } else {
+                    *target
+                }
+            }),
+
+            Instr::Jump(target) => Ok(*target),
+        }
+    }
+
+    /// Locates `err` at the instruction `pc` and notes every block it is
+    /// nested in, innermost first. A branch belongs to the block it guards
+    /// rather than being nested in it.
+    fn backtrace(&self, err: Error, stack: &Stack, pc: Addr) -> Error {
+        let bytecode = self.bytecode;
+        let mut err = locate_error(err, stack, &bytecode.locs[pc]);
+
+        let mut parent = bytecode.parents[pc];
+        if let Instr::Branch { .. } = bytecode.instrs[pc] {
+            parent = parent.and_then(|id| bytecode.blocks[id].parent);
+        }
+        while let Some(block) = parent.map(|id| &bytecode.blocks[id]) {
+            err = err.push_info(InfoKind::InBlock(block.marker), block.loc.clone());
+            parent = block.parent;
+        }
+
+        err
+    }
+}
@@ -0,0 +1,180 @@
+//! Runs every program in `porth/tests` with each way of simulating it, which
+//! must all print the same output and leave the same stack.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use std::sync::Arc;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use porrs::{Arity, Category, Natives, OverflowPolicy};
+use porrs::{Bytecode, Program, Simulator, Vm};
+
+/// Output and final stack of a program
+type Run = (String, Vec<u64>);
+
+fn porth_tests() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../porth/tests");
+
+    let mut paths = fs::read_dir(dir)
+        .expect("porth/tests should exist")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "porth"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    assert!(!paths.is_empty(), "porth/tests has no programs");
+    paths
+}
+
+fn simulate(program: &Program) -> Run {
+    let mut output = Vec::new();
+    let outcome = Simulator::new(program)
+        .with_output(&mut output)
+        .run()
+        .unwrap();
+
+    (String::from_utf8(output).unwrap(), outcome.stack)
+}
+
+fn interpret(bytecode: &Bytecode) -> Run {
+    let mut output = Vec::new();
+    let outcome = Vm::new(bytecode).with_output(&mut output).run().unwrap();
+
+    (String::from_utf8(output).unwrap(), outcome.stack)
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn jit(bytecode: &Bytecode) -> Run {
+    let jit = porrs::Jit::compile(bytecode).unwrap();
+
+    let mut output = Vec::new();
+    let outcome = Vm::new(bytecode)
+        .with_output(&mut output)
+        .with_jit(&jit)
+        .run()
+        .unwrap();
+
+    (String::from_utf8(output).unwrap(), outcome.stack)
+}
+
+/// Output of a program, the stack it stopped with, and either the code it gave
+/// to `exit` or the category of the error it failed with
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+type Ending = (String, Vec<u64>, Result<Option<i32>, Category>);
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn simulate_to_end(program: &Program, overflow: OverflowPolicy) -> Ending {
+    let mut output = Vec::new();
+    let mut simulator = Simulator::new(program)
+        .with_output(&mut output)
+        .with_overflow(overflow);
+
+    let (stack, result) = match simulator.run() {
+        Ok(outcome) => (outcome.stack, Ok(outcome.exit_code)),
+        Err(err) => (simulator.stack().to_vec(), Err(err.category())),
+    };
+    drop(simulator);
+
+    (String::from_utf8(output).unwrap(), stack, result)
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn jit_to_end(bytecode: &Bytecode, overflow: OverflowPolicy) -> Ending {
+    let jit = porrs::Jit::compile(bytecode).unwrap();
+
+    let mut output = Vec::new();
+    let mut vm = Vm::new(bytecode)
+        .with_output(&mut output)
+        .with_overflow(overflow)
+        .with_jit(&jit);
+
+    let (stack, result) = match vm.run() {
+        Ok(outcome) => (outcome.stack, Ok(outcome.exit_code)),
+        Err(err) => (vm.stack().to_vec(), Err(err.category())),
+    };
+    drop(vm);
+
+    (String::from_utf8(output).unwrap(), stack, result)
+}
+
+#[test]
+fn bytecode_matches_simulator() {
+    for path in porth_tests() {
+        let program = Program::from_path(&path).unwrap();
+        let bytecode = Bytecode::from_program(&program);
+
+        assert_eq!(
+            interpret(&bytecode),
+            simulate(&program),
+            "{}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn loaded_bytecode_matches_simulator() {
+    for path in porth_tests() {
+        let program = Program::from_path(&path).unwrap();
+        let bytes = Bytecode::from_program(&program).to_bytes();
+        let bytecode = Bytecode::from_bytes(&bytes, Default::default()).unwrap();
+
+        assert_eq!(
+            interpret(&bytecode),
+            simulate(&program),
+            "{}",
+            path.display()
+        );
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn jit_matches_simulator() {
+    for path in porth_tests() {
+        let program = Program::from_path(&path).unwrap();
+        let bytecode = Bytecode::from_program(&program);
+
+        assert_eq!(jit(&bytecode), simulate(&program), "{}", path.display());
+    }
+}
+
+/// Programs which make the JIT trap back into the interpreter
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn jit_fallbacks_match_simulator() {
+    use OverflowPolicy::{Trap, Warn, Wrap};
+
+    let mut natives = Natives::new();
+    natives
+        .register("square", Arity::new(1, 1), |ctx| {
+            let val = ctx.pop()?;
+            ctx.push(val * val);
+            Ok(())
+        })
+        .unwrap();
+    let natives = Arc::new(natives);
+
+    let failed = Err(Category::Simulation);
+    let cases = [
+        ("underflow", "1 print +", Wrap, failed),
+        ("wrapping", "1 0 1 - + print", Warn, Ok(None)),
+        ("overflow", "7 18446744073709551615 1 +", Trap, failed),
+        ("division by zero", "7 1 0 divmod", Wrap, failed),
+        ("exit", "1 print 2 3 exit 4 print", Wrap, Ok(Some(3))),
+        ("non-binary if", "2 if 1 print end", Wrap, Ok(None)),
+        ("non-binary do", "3 while do 0 end", Wrap, Ok(None)),
+        ("native", "3 square dup print square", Wrap, Ok(None)),
+        ("native underflow", "square", Wrap, failed),
+    ];
+
+    for (name, source, overflow, expected) in cases {
+        let program = Program::from_source_with(name, source, Arc::clone(&natives)).unwrap();
+        let bytecode = Bytecode::from_program(&program);
+
+        let ending = simulate_to_end(&program, overflow);
+        assert_eq!(ending.2, expected, "{}", name);
+        assert_eq!(jit_to_end(&bytecode, overflow), ending, "{}", name);
+    }
+}
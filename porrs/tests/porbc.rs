@@ -0,0 +1,131 @@
+//! Decoding of damaged `.porbc` files, which has to fail without panicking.
+
+use porrs::{Bytecode, ErrorKind, LoadingError, Marker, Program, PORBC_MAGIC, PORBC_VERSION};
+
+const SOURCE: &str = "
+3 while dup do
+    dup 2 divmod drop if
+        dup print
+    else 1 if*
+        0 print
+    end
+    1 -
+end
+drop
+";
+
+/// `BRANCH_SOURCE` encodes its instructions last. Its branch is followed by
+/// a 21 byte push and a 14 byte intrinsic, and is itself made of the opcode,
+/// a u8 marker, a u32 target, a u32 location, a u32 block and a u32
+/// operation.
+const BRANCH_SOURCE: &str = "1 if 2 print end";
+const BRANCH_FROM_END: usize = 18 + 21 + 14;
+
+fn encode(source: &str) -> Vec<u8> {
+    let program = Program::from_source("<test>", source).unwrap();
+    Bytecode::from_program(&program).to_bytes()
+}
+
+fn decode(bytes: &[u8]) -> porrs::Result<Bytecode> {
+    Bytecode::from_bytes(bytes, Default::default())
+}
+
+macro_rules! assert_rejected {
+    ($bytes:expr, $pattern:pat $(if $guard:expr)?) => {
+        match decode($bytes).map(|_| ()).unwrap_err().kind() {
+            ErrorKind::Loading($pattern) $(if $guard)? => {}
+            kind => panic!("unexpected error: {}", kind),
+        }
+    };
+}
+
+#[test]
+fn round_trips() {
+    let bytes = encode(SOURCE);
+    assert_eq!(decode(&bytes).unwrap().to_bytes(), bytes);
+}
+
+#[test]
+fn rejects_every_truncation() {
+    let bytes = encode(SOURCE);
+
+    for len in 0..bytes.len() {
+        assert!(decode(&bytes[..len]).is_err(), "truncated to {} bytes", len);
+    }
+
+    assert_rejected!(
+        &bytes[..bytes.len() - 1],
+        LoadingError::Truncated("instructions")
+    );
+}
+
+#[test]
+fn rejects_other_files() {
+    assert_rejected!(b"", LoadingError::NotBytecode);
+    assert_rejected!(b"PORSNAP\0", LoadingError::NotBytecode);
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut bytes = encode(SOURCE);
+    let version = PORBC_VERSION + 1;
+    bytes[PORBC_MAGIC.len()..PORBC_MAGIC.len() + 2].copy_from_slice(&version.to_le_bytes());
+
+    assert_rejected!(
+        &bytes,
+        LoadingError::UnsupportedVersion { found, expected } if *found == version && *expected == PORBC_VERSION
+    );
+}
+
+#[test]
+fn rejects_trailing_bytes() {
+    let mut bytes = encode(SOURCE);
+    bytes.extend([0; 3]);
+
+    assert_rejected!(&bytes, LoadingError::TrailingBytes(3));
+}
+
+#[test]
+fn survives_every_corrupted_byte() {
+    let bytes = encode(SOURCE);
+
+    for at in 0..bytes.len() {
+        for flip in [0x01, 0x80, 0xff] {
+            let mut corrupted = bytes.clone();
+            corrupted[at] ^= flip;
+
+            // Some corruptions still decode, e.g. of a pushed value, but none
+            // may panic
+            let _ = decode(&corrupted);
+        }
+    }
+}
+
+#[test]
+fn rejects_branches_outside_of_blocks() {
+    let mut bytes = encode(BRANCH_SOURCE);
+    let branch = bytes.len() - BRANCH_FROM_END;
+    assert_eq!(bytes[branch], 3, "expected a branch opcode");
+
+    bytes[branch + 10..branch + 14].copy_from_slice(&u32::MAX.to_le_bytes());
+
+    assert_rejected!(&bytes, LoadingError::MisplacedBranch(Marker::If));
+}
+
+#[test]
+fn rejects_markers_which_do_not_branch() {
+    let mut bytes = encode(BRANCH_SOURCE);
+    let branch = bytes.len() - BRANCH_FROM_END;
+    assert_eq!(bytes[branch], 3, "expected a branch opcode");
+
+    // Markers are numbered `if`, `if*`, `else`, ...
+    bytes[branch + 1] = 2;
+
+    assert_rejected!(
+        &bytes,
+        LoadingError::InvalidValue {
+            what: "branch marker",
+            value: 2
+        }
+    );
+}
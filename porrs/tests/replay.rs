@@ -0,0 +1,248 @@
+//! Recording the input read by natives, replaying it, and decoding recordings.
+
+use std::sync::Arc;
+
+use porrs::{
+    Arity, Divergence, ErrorKind, LoadingError, Natives, Program, Recording, SimulationError,
+    Simulator, RECORDING_MAGIC,
+};
+
+/// Natives reading input in different ways, the same word reading `take`
+/// bytes at once
+fn natives(take: usize) -> Arc<Natives> {
+    let mut natives = Natives::new();
+    natives
+        .register("read-line", Arity::new(0, 1), |ctx| {
+            let mut line = String::new();
+            ctx.input().read_line(&mut line)?;
+            ctx.push(line.len() as u64);
+            Ok(())
+        })
+        .unwrap()
+        .register("peek", Arity::new(0, 1), |ctx| {
+            let len = ctx.input().fill_buf()?.len();
+            ctx.push(len as u64);
+            Ok(())
+        })
+        .unwrap()
+        .register("read-all", Arity::new(0, 1), |ctx| {
+            let mut all = Vec::new();
+            ctx.input().read_to_end(&mut all)?;
+            ctx.push(all.len() as u64);
+            Ok(())
+        })
+        .unwrap()
+        .register("take", Arity::new(0, 0), move |ctx| {
+            let mut buf = vec![0; take];
+            ctx.input().read_exact(&mut buf)?;
+            Ok(())
+        })
+        .unwrap();
+    Arc::new(natives)
+}
+
+fn program(source: &str, natives: Arc<Natives>) -> Program {
+    Program::from_source_with("<test>", source, natives).unwrap()
+}
+
+fn record(program: &Program, input: &str) -> (Vec<u64>, Recording) {
+    let mut simulator = Simulator::new(program)
+        .with_input(input.as_bytes())
+        .record_input();
+    let outcome = simulator.run().unwrap();
+
+    (outcome.stack, simulator.recording().unwrap().clone())
+}
+
+fn replay(program: &Program, recording: Recording) -> porrs::Result<Vec<u64>> {
+    Ok(Simulator::new(program).with_replay(recording).run()?.stack)
+}
+
+/// Native, bytes seen, bytes consumed and end of input flag of each read,
+/// decoded from the documented format
+fn reads(recording: &Recording) -> Vec<(String, Vec<u8>, u64, bool)> {
+    let bytes = recording.to_bytes();
+    let mut rest = &bytes[RECORDING_MAGIC.len() + 2..];
+
+    let mut take = |len: usize| {
+        let (taken, tail) = rest.split_at(len);
+        rest = tail;
+        taken.to_vec()
+    };
+    let number = |bytes: Vec<u8>| u64::from_le_bytes(bytes.try_into().unwrap());
+
+    let mut reads = Vec::new();
+    for _ in 0..number(take(8)) {
+        let len = number(take(8)) as usize;
+        let native = String::from_utf8(take(len)).unwrap();
+        let len = number(take(8)) as usize;
+        let seen = take(len);
+        let consumed = number(take(8));
+        let eof = take(1) == [1];
+        reads.push((native, seen, consumed, eof));
+    }
+    reads
+}
+
+macro_rules! assert_diverged {
+    ($result:expr, $pattern:pat $(if $guard:expr)?) => {
+        match $result.unwrap_err().kind() {
+            ErrorKind::Simulation(SimulationError::ReplayDiverged($pattern)) $(if $guard)? => {}
+            kind => panic!("unexpected error: {}", kind),
+        }
+    };
+}
+
+macro_rules! assert_rejected {
+    ($bytes:expr, $pattern:pat) => {
+        match Recording::from_bytes($bytes).unwrap_err().kind() {
+            ErrorKind::Loading($pattern) => {}
+            kind => panic!("unexpected error: {}", kind),
+        }
+    };
+}
+
+#[test]
+fn records_peeked_bytes_without_consuming_them() {
+    let program = program("peek read-line", natives(1));
+    let (stack, recording) = record(&program, "12\n34\n");
+
+    assert_eq!(stack, [6, 3]);
+    assert_eq!(
+        reads(&recording),
+        [
+            ("peek".to_owned(), b"12\n34\n".to_vec(), 0, false),
+            ("read-line".to_owned(), b"12\n34\n".to_vec(), 3, false),
+        ]
+    );
+    assert_eq!(replay(&program, recording).unwrap(), stack);
+}
+
+#[test]
+fn records_the_end_of_input() {
+    let program = program("read-line read-all read-all", natives(1));
+    let (stack, recording) = record(&program, "12\n34");
+
+    assert_eq!(stack, [3, 2, 0]);
+    let eofs = reads(&recording)
+        .into_iter()
+        .map(|(_, _, _, eof)| eof)
+        .collect::<Vec<_>>();
+    assert_eq!(eofs, [false, true, true]);
+    assert_eq!(replay(&program, recording).unwrap(), stack);
+}
+
+#[test]
+fn skips_natives_which_read_nothing() {
+    // Taking no bytes never touches the input
+    let program = program("take peek take", natives(0));
+    let (_, recording) = record(&program, "1\n");
+
+    assert_eq!(recording.len(), 1);
+}
+
+#[test]
+fn detects_reads_by_other_natives() {
+    let (_, recording) = record(&program("read-line", natives(1)), "1\n");
+
+    assert_diverged!(
+        replay(&program("read-all", natives(1)), recording),
+        Divergence::UnexpectedNative { expected, found }
+            if expected == "read-line" && found == "read-all"
+    );
+}
+
+#[test]
+fn detects_reads_past_the_last_recorded_one() {
+    let (_, recording) = record(&program("read-line", natives(1)), "1\n2\n");
+
+    assert_diverged!(
+        replay(&program("read-line read-line", natives(1)), recording),
+        Divergence::Exhausted(name) if name == "read-line"
+    );
+}
+
+#[test]
+fn detects_reads_past_the_recorded_bytes() {
+    let (_, recording) = record(&program("take", natives(1)), "12");
+
+    assert_diverged!(
+        replay(&program("take", natives(2)), recording),
+        Divergence::ReadPastRecording(name) if name == "take"
+    );
+}
+
+#[test]
+fn detects_different_consumption() {
+    let (_, recording) = record(&program("take", natives(2)), "12\n");
+
+    assert_diverged!(
+        replay(&program("take", natives(1)), recording),
+        Divergence::Consumed { name, expected: 2, found: 1 } if name == "take"
+    );
+}
+
+#[test]
+fn detects_unused_reads() {
+    let (_, recording) = record(&program("read-line read-line", natives(1)), "1\n2\n");
+
+    assert_diverged!(
+        replay(&program("read-line", natives(1)), recording),
+        Divergence::Unused(1)
+    );
+}
+
+#[test]
+fn round_trips() {
+    let (_, recording) = record(&program("peek read-line read-all", natives(1)), "1\n2");
+
+    let bytes = recording.to_bytes();
+    assert_eq!(Recording::from_bytes(&bytes).unwrap(), recording);
+}
+
+#[test]
+fn rejects_damaged_recordings() {
+    let (_, recording) = record(&program("read-line", natives(1)), "1\n");
+    let bytes = recording.to_bytes();
+
+    for len in 0..bytes.len() {
+        assert!(
+            Recording::from_bytes(&bytes[..len]).is_err(),
+            "truncated to {} bytes",
+            len
+        );
+    }
+    assert_rejected!(&bytes[..bytes.len() - 1], LoadingError::Truncated("reads"));
+
+    assert_rejected!(b"PORBC\0", LoadingError::NotRecording);
+
+    let mut version = bytes.clone();
+    version[RECORDING_MAGIC.len()] ^= 0xff;
+    assert_rejected!(&version, LoadingError::UnsupportedVersion { .. });
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_rejected!(&trailing, LoadingError::TrailingBytes(1));
+
+    let mut eof = bytes.clone();
+    *eof.last_mut().unwrap() = 2;
+    assert_rejected!(
+        &eof,
+        LoadingError::InvalidValue {
+            what: "end of input flag",
+            value: 2
+        }
+    );
+
+    // The consumed byte count precedes the end of input flag
+    let mut consumed = bytes;
+    let at = consumed.len() - 9;
+    consumed[at..at + 8].copy_from_slice(&3u64.to_le_bytes());
+    assert_rejected!(
+        &consumed,
+        LoadingError::InvalidValue {
+            what: "consumed byte count",
+            value: 3
+        }
+    );
+}
@@ -0,0 +1,127 @@
+//! Taking, decoding and resuming snapshots, including damaged ones.
+
+use porrs::{Bytecode, ErrorKind, LoadingError, Program, Snapshot, Vm, SNAPSHOT_MAGIC};
+
+const SOURCE: &str = "10 while dup do dup print 1 - end drop";
+
+fn bytecode(name: &str, source: &str) -> Bytecode {
+    Bytecode::from_program(&Program::from_source(name, source).unwrap())
+}
+
+/// Snapshot of `bytecode` after `executed` instructions
+fn take(bytecode: &Bytecode, executed: u64) -> Snapshot {
+    let mut vm = Vm::new(bytecode).with_output(Vec::new());
+    assert!(vm.run_until(|n| n >= executed).unwrap().is_none());
+    vm.snapshot()
+}
+
+fn resume(bytecode: &Bytecode, snapshot: Snapshot) -> porrs::Result<String> {
+    let mut output = Vec::new();
+    Vm::new(bytecode)
+        .with_output(&mut output)
+        .with_snapshot(snapshot)?
+        .run()?;
+    Ok(String::from_utf8(output).unwrap())
+}
+
+macro_rules! assert_rejected {
+    ($result:expr, $pattern:pat) => {
+        match $result.map(|_| ()).unwrap_err().kind() {
+            ErrorKind::Loading($pattern) => {}
+            kind => panic!("unexpected error: {}", kind),
+        }
+    };
+}
+
+#[test]
+fn resumes_where_it_was_taken() {
+    let bytecode = bytecode("loop.porth", SOURCE);
+
+    let mut output = Vec::new();
+    let mut vm = Vm::new(&bytecode).with_output(&mut output);
+    assert!(vm.run_until(|n| n >= 20).unwrap().is_none());
+    let snapshot = Snapshot::from_bytes(&vm.snapshot().to_bytes()).unwrap();
+    drop(vm);
+
+    let expected = (1..=10)
+        .rev()
+        .map(|n| format!("{} ({:#018x})\n", n, n))
+        .collect::<String>();
+    let before = String::from_utf8(output).unwrap();
+    let after = resume(&bytecode, snapshot).unwrap();
+
+    assert_eq!(before + &after, expected);
+}
+
+#[test]
+fn resumes_the_same_program_from_another_path() {
+    let snapshot = take(&bytecode("loop.porth", SOURCE), 20);
+
+    assert!(resume(&bytecode("./loop.porth", SOURCE), snapshot.clone()).is_ok());
+    assert!(resume(&bytecode("/tmp/loop.porth", SOURCE), snapshot).is_ok());
+}
+
+#[test]
+fn rejects_other_programs() {
+    let snapshot = take(&bytecode("loop.porth", SOURCE), 20);
+    let other = bytecode("loop.porth", "10 while dup do dup print 2 - end drop");
+
+    assert_rejected!(resume(&other, snapshot), LoadingError::SnapshotMismatch);
+}
+
+#[test]
+fn rejects_every_truncation() {
+    let bytes = take(&bytecode("loop.porth", SOURCE), 20).to_bytes();
+
+    for len in 0..bytes.len() {
+        assert!(
+            Snapshot::from_bytes(&bytes[..len]).is_err(),
+            "truncated to {} bytes",
+            len
+        );
+    }
+
+    assert_rejected!(
+        Snapshot::from_bytes(&bytes[..bytes.len() - 1]),
+        LoadingError::Truncated("stack")
+    );
+}
+
+#[test]
+fn rejects_other_files() {
+    assert_rejected!(Snapshot::from_bytes(b""), LoadingError::NotSnapshot);
+    assert_rejected!(Snapshot::from_bytes(b"PORBC\0"), LoadingError::NotSnapshot);
+}
+
+#[test]
+fn rejects_corrupted_headers_and_positions() {
+    let bytecode = bytecode("loop.porth", SOURCE);
+    let bytes = take(&bytecode, 20).to_bytes();
+
+    let mut version = bytes.clone();
+    version[SNAPSHOT_MAGIC.len()] ^= 0xff;
+    assert_rejected!(
+        Snapshot::from_bytes(&version),
+        LoadingError::UnsupportedVersion { .. }
+    );
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_rejected!(
+        Snapshot::from_bytes(&trailing),
+        LoadingError::TrailingBytes(1)
+    );
+
+    // The position of the next instruction follows the u16 version and the
+    // u64 fingerprint
+    let mut pc = bytes.clone();
+    let at = SNAPSHOT_MAGIC.len() + 2 + 8;
+    pc[at..at + 8].copy_from_slice(&u64::from(u32::MAX).to_le_bytes());
+    let snapshot = Snapshot::from_bytes(&pc).unwrap();
+    assert_rejected!(resume(&bytecode, snapshot), LoadingError::OutOfRange { .. });
+
+    let mut fingerprint = bytes;
+    fingerprint[SNAPSHOT_MAGIC.len() + 2] ^= 0x01;
+    let snapshot = Snapshot::from_bytes(&fingerprint).unwrap();
+    assert_rejected!(resume(&bytecode, snapshot), LoadingError::SnapshotMismatch);
+}